@@ -14,6 +14,7 @@
 #include "mmu.h"
 #include "proc.h"
 #include "x86.h"
+#include "poll.h"
 
 static void consputc(int);
 
@@ -232,6 +233,7 @@ void consoleintr(int (*getc)(void)) {
         if (c == '\n' || c == C('D') || input.e == input.r + INPUT_BUF) {
           input.w = input.e;
           wakeup(&input.r);
+          pollwakeup();
         }
       }
       break;
@@ -292,11 +294,23 @@ int consolewrite(struct inode *ip, char *buf, int n) {
   return n;
 }
 
+int consolepoll(struct inode *ip) {
+  int ev;
+
+  ev = POLLOUT;
+  acquire(&cons.lock);
+  if (input.r != input.w)
+    ev |= POLLIN;
+  release(&cons.lock);
+  return ev;
+}
+
 void consoleinit(void) {
   initlock(&cons.lock, "console");
 
   devsw[CONSOLE].write = consolewrite;
   devsw[CONSOLE].read = consoleread;
+  devsw[CONSOLE].poll = consolepoll;
   cons.locking = 1;
 
   ioapicenable(IRQ_KBD, 0);
@@ -32,8 +32,14 @@ void fileclose(struct file *);
 struct file *filedup(struct file *);
 void fileinit(void);
 int fileread(struct file *, char *, int n);
+int filepoll(struct file *);
+int filereadat(struct file *, char *, uint, int);
 int filestat(struct file *, struct stat *);
 int filewrite(struct file *, char *, int n);
+uint pollseq(void);
+void pollsleep(uint, int);
+void polltick(void);
+void pollwakeup(void);
 
 // fs.c
 void readsb(int dev, struct superblock *sb);
@@ -92,6 +98,19 @@ void end_op();
 extern int ismp;
 void mpinit(void);
 
+// net.rs
+void epollclose(int);
+int epollpoll(int);
+int sockalloc(int, int, int, int);
+void sockclose(int);
+void sockexit(int);
+void sockfork(int, int);
+int sockowned(int, int);
+int sockpair(int, int, int, int, int *);
+int sockpoll(int);
+int sockread(int, char *, int);
+int sockwrite(int, char *, int);
+
 // picirq.c
 void picenable(int);
 void picinit(void);
@@ -99,6 +118,7 @@ void picinit(void);
 // pipe.c
 int pipealloc(struct file **, struct file **);
 void pipeclose(struct pipe *, int);
+int pipepoll(struct pipe *, int);
 int piperead(struct pipe *, char *, int);
 int pipewrite(struct pipe *, char *, int);
 
@@ -109,6 +129,7 @@ void exit(void);
 int fork(void);
 int growproc(int);
 int kill(int);
+int killed(void);
 struct cpu *mycpu(void);
 struct proc *myproc();
 void pinit(void);
@@ -117,6 +138,7 @@ void scheduler(void) __attribute__((noreturn));
 void sched(void);
 void setproc(struct proc *);
 void sleep(void *, struct spinlock *);
+void sleepunlock(void *, void (*)(void *), void *);
 void userinit(void);
 int wait(void);
 void wakeup(void *);
@@ -154,9 +176,20 @@ int argint(int, int *);
 int argptr(int, char **, int);
 int argstr(int, char **);
 int fetchint(uint, int *);
+int fetchptr(uint, char **, int);
 int fetchstr(uint, char **);
 void syscall(void);
 
+// sysfile.c
+int argepoll(int, int *);
+int argfile(int, struct file **);
+int argsock(int, int *);
+struct inode *sockcreate(char *);
+int epollfdalloc(int);
+int sockfdalloc(int);
+void sockiput(struct inode *);
+struct inode *socklookup(char *);
+
 // timer.c
 void timerinit(void);
 
@@ -0,0 +1,22 @@
+#define EPOLLIN 0x001      // Data may be read without blocking
+#define EPOLLOUT 0x004     // Data may be written without blocking
+#define EPOLLERR 0x008     // Error condition (always reported)
+#define EPOLLHUP 0x010     // Hung up (always reported)
+#define EPOLLET 0x80000000 // Report events as they happen, not while they last
+
+// Operations of epoll_ctl(...).
+#define EPOLL_CTL_ADD 1 // Register a socket
+#define EPOLL_CTL_DEL 2 // Unregister a socket
+#define EPOLL_CTL_MOD 3 // Change the events and data of a registered socket
+
+typedef union epoll_data {
+  void *ptr;
+  int fd;
+  uint u32;
+} epoll_data_t;
+
+// The events a socket is registered for, or has ready.
+struct epoll_event {
+  uint events;       // EPOLLIN, EPOLLOUT, ... and EPOLLET
+  epoll_data_t data; // Returned with the events, ignored by the kernel
+};
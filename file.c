@@ -5,10 +5,14 @@
 #include "types.h"
 #include "defs.h"
 #include "param.h"
+#include "mmu.h"
+#include "proc.h"
 #include "fs.h"
 #include "spinlock.h"
 #include "sleeplock.h"
 #include "file.h"
+#include "stat.h"
+#include "poll.h"
 
 struct devsw devsw[NDEV];
 struct {
@@ -16,7 +20,26 @@ struct {
   struct file file[NFILE];
 } ftable;
 
-void fileinit(void) { initlock(&ftable.lock, "ftable"); }
+// Processes blocked in poll() sleep on pollwait.seq, or on
+// pollwait.timed if they have a timeout. pollwakeup() is called
+// whenever a pipe, the console or a socket may have become ready.
+struct {
+  struct spinlock lock;
+  uint seq;
+  int timed;
+} pollwait;
+
+// Is the socket of file f owned by the calling process? Like the
+// socket system calls, read, write and poll reject sockets of
+// other processes.
+static int sockmine(struct file *f) {
+  return sockowned(f->sock, myproc()->pid);
+}
+
+void fileinit(void) {
+  initlock(&ftable.lock, "ftable");
+  initlock(&pollwait.lock, "pollwait");
+}
 
 // Allocate a file structure.
 struct file *filealloc(void) {
@@ -66,7 +89,10 @@ void fileclose(struct file *f) {
     begin_op();
     iput(ff.ip);
     end_op();
-  }
+  } else if (ff.type == FD_SOCKET)
+    sockclose(ff.sock);
+  else if (ff.type == FD_EPOLL)
+    epollclose(ff.epoll);
 }
 
 // Get metadata about file f.
@@ -80,6 +106,76 @@ int filestat(struct file *f, struct stat *st) {
   return -1;
 }
 
+// Report which poll events are currently ready on file f.
+int filepoll(struct file *f) {
+  int ev;
+  short type, major;
+
+  // Epoll descriptors are neither read nor written, but report POLLIN
+  // while a registered socket has events.
+  if (f->type == FD_EPOLL)
+    return epollpoll(f->epoll);
+
+  ev = 0;
+  if (f->type == FD_PIPE)
+    ev = pipepoll(f->pipe, f->writable);
+  else if (f->type == FD_SOCKET)
+    ev = sockmine(f) ? sockpoll(f->sock) : POLLNVAL;
+  else if (f->type == FD_INODE) {
+    ilock(f->ip);
+    type = f->ip->type;
+    major = f->ip->major;
+    iunlock(f->ip);
+    if (type == T_DEV && major >= 0 && major < NDEV && devsw[major].poll)
+      ev = devsw[major].poll(f->ip);
+    else
+      ev = POLLIN | POLLOUT; // Inodes never block.
+  }
+
+  if (!f->readable)
+    ev &= ~POLLIN;
+  if (!f->writable)
+    ev &= ~POLLOUT;
+  return ev;
+}
+
+// Return the current poll sequence number. A poller samples it
+// before checking its files and passes it to pollsleep().
+uint pollseq(void) {
+  uint seq;
+
+  acquire(&pollwait.lock);
+  seq = pollwait.seq;
+  release(&pollwait.lock);
+  return seq;
+}
+
+// Sleep until pollwakeup() is called, unless it has already been
+// called since seq was sampled. Timed pollers are also woken by
+// polltick() on every clock tick.
+void pollsleep(uint seq, int timed) {
+  acquire(&pollwait.lock);
+  if (pollwait.seq == seq) {
+    if (timed)
+      sleep(&pollwait.timed, &pollwait.lock);
+    else
+      sleep(&pollwait.seq, &pollwait.lock);
+  }
+  release(&pollwait.lock);
+}
+
+// Wake all processes blocked in poll().
+void pollwakeup(void) {
+  acquire(&pollwait.lock);
+  pollwait.seq++;
+  wakeup(&pollwait.seq);
+  wakeup(&pollwait.timed);
+  release(&pollwait.lock);
+}
+
+// Wake processes blocked in poll() with a timeout.
+void polltick(void) { wakeup(&pollwait.timed); }
+
 // Read from file f.
 int fileread(struct file *f, char *addr, int n) {
   int r;
@@ -88,6 +184,8 @@ int fileread(struct file *f, char *addr, int n) {
     return -1;
   if (f->type == FD_PIPE)
     return piperead(f->pipe, addr, n);
+  if (f->type == FD_SOCKET)
+    return sockmine(f) ? sockread(f->sock, addr, n) : -1;
   if (f->type == FD_INODE) {
     ilock(f->ip);
     if ((r = readi(f->ip, addr, f->off, n)) > 0)
@@ -98,6 +196,25 @@ int fileread(struct file *f, char *addr, int n) {
   panic("fileread");
 }
 
+// Read n bytes of regular file f at offset off, leaving the file
+// offset alone. Used by sendfile() to read through the buffer cache
+// straight into the kernel.
+int filereadat(struct file *f, char *addr, uint off, int n) {
+  int r;
+
+  if (f->readable == 0 || f->type != FD_INODE)
+    return -1;
+  ilock(f->ip);
+  if (f->ip->type != T_FILE) {
+    iunlock(f->ip);
+    return -1;
+  }
+  // Reading past the end of the file is end-of-file, not an error.
+  r = off > f->ip->size ? 0 : readi(f->ip, addr, off, n);
+  iunlock(f->ip);
+  return r;
+}
+
 // PAGEBREAK!
 // Write to file f.
 int filewrite(struct file *f, char *addr, int n) {
@@ -107,6 +224,8 @@ int filewrite(struct file *f, char *addr, int n) {
     return -1;
   if (f->type == FD_PIPE)
     return pipewrite(f->pipe, addr, n);
+  if (f->type == FD_SOCKET)
+    return sockmine(f) ? sockwrite(f->sock, addr, n) : -1;
   if (f->type == FD_INODE) {
     // write a few blocks at a time to avoid exceeding
     // the maximum log transaction size, including
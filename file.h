@@ -1,11 +1,13 @@
 struct file {
-  enum { FD_NONE, FD_PIPE, FD_INODE } type;
+  enum { FD_NONE, FD_PIPE, FD_INODE, FD_SOCKET, FD_EPOLL } type;
   int ref; // reference count
   char readable;
   char writable;
   struct pipe *pipe;
   struct inode *ip;
   uint off;
+  int sock; // network stack socket handle
+  int epoll; // network stack epoll instance handle
 };
 
 // in-memory copy of an inode
@@ -29,6 +31,7 @@ struct inode {
 struct devsw {
   int (*read)(struct inode *, char *, int);
   int (*write)(struct inode *, char *, int);
+  int (*poll)(struct inode *);
 };
 
 extern struct devsw devsw[];
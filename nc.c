@@ -1,5 +1,7 @@
 #include "types.h"
 #include "user.h"
+#include "poll.h"
+#include "socket.h"
 
 enum mode { MODE_SEND, MODE_LISTEN, MODE_UNKNOWN };
 
@@ -14,37 +16,10 @@ enum mode parse_mode(char *mode) {
   return MODE_UNKNOWN;
 }
 
-// Parse the hexadecimal representation of an IPV4 address from its 'dot'
-// representation.
-uint parse_addr(char *addr) {
-  uint target_addr = 0x0;
-  uint shift = 24;
-  uint addr_len = strlen(addr);
-  for (int i = 0; i < addr_len; i++) {
-    char part[4] = {0x0, 0x0, 0x0, 0x0};
-    int sep = i;
-    for (int j = i; j < addr_len; j++) {
-      if (addr[j] == '.') {
-        sep = j;
-        break;
-      }
-      sep = j + 1; // No separator after last octet.
-    }
-    memmove(&part, addr + i, sep - i);
-
-    int octet = atoi(part);
-    target_addr |= octet << (shift);
-    shift -= 8;
-
-    i = sep;
-  }
-  return target_addr;
-}
-
 // A simple `nc` like program. As we have a UDP only network stack, the `-l`
 // flag is somewhat implicit.
 int main(int argc, char *argv[]) {
-  if (argc < 3) {
+  if (argc < 4) {
     printf(2, usage);
     exit();
   }
@@ -57,33 +32,65 @@ int main(int argc, char *argv[]) {
   }
 
   // Parse the address and port.
-  uint addr = parse_addr(argv[2]);
   int port = atoi(argv[3]);
+  struct sockaddr_in addr;
+  memset(&addr, 0, sizeof(addr));
+  addr.sin_family = AF_INET;
+  addr.sin_port = htons(port);
+  addr.sin_addr.s_addr = inet_addr(argv[2]);
 
   // Open a new socket and setup the send/receive buffer.
-  int sockfd = socket(0);
+  int sockfd = socket(AF_INET, SOCK_DGRAM, 0);
+  if (sockfd < 0) {
+    printf(2, "nc: cannot create socket\n");
+    exit();
+  }
   const uint buf_size = 1024;
   char *buf = malloc(buf_size);
 
   if (mode == MODE_SEND) {
     // Set up the socket with details of the remote address and port.
-    connect(sockfd, addr, port);
-    // Send data from stdin to the socket.
+    int r = connect(sockfd, (struct sockaddr *)&addr, sizeof(addr));
+    if (r < 0) {
+      printf(2, "nc: cannot connect (%d)\n", -r);
+      exit();
+    }
+    // Send data from stdin to the socket and print any replies, waiting on
+    // whichever becomes ready first.
+    struct pollfd fds[2];
+    fds[0].fd = 0;
+    fds[0].events = POLLIN;
+    fds[1].fd = sockfd;
+    fds[1].events = POLLIN;
     for (;;) {
-      int bytes_read = read(1, buf, buf_size);
-      send(sockfd, buf, bytes_read);
+      if (poll(fds, 2, -1) < 0)
+        break;
+      if (fds[0].revents & POLLIN) {
+        int bytes_read = read(0, buf, buf_size);
+        if (bytes_read <= 0)
+          break;
+        send(sockfd, buf, bytes_read, 0);
+      }
+      if (fds[1].revents & (POLLIN | POLLHUP)) {
+        int bytes_read = recv(sockfd, buf, buf_size - 1, 0);
+        if (bytes_read <= 0)
+          break;
+        buf[bytes_read] = '\x00';
+        printf(1, "%s", buf);
+      }
     }
   } else if (mode == MODE_LISTEN) {
     // Bind the socket to the specified local address and port.
-    bind(sockfd, addr, port);
-    // Read data from the socket.
+    if (bind(sockfd, (struct sockaddr *)&addr, sizeof(addr)) != 0) {
+      printf(2, "nc: cannot bind port %d\n", port);
+      exit();
+    }
+    // Read data from the socket, blocking until it arrives.
     for (;;) {
-      int bytes_read = recv(sockfd, buf, buf_size - 1);
+      int bytes_read = recv(sockfd, buf, buf_size - 1, 0);
       if (bytes_read > 0) {
         buf[bytes_read] = '\x00';
         printf(1, "%s", buf);
-      } else {
-        sleep(1);
       }
     }
   }
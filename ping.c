@@ -0,0 +1,100 @@
+#include "types.h"
+#include "user.h"
+#include "socket.h"
+
+#define ICMP_ECHO_REPLY 0
+#define ICMP_ECHO_REQUEST 8
+#define PAYLOAD_SIZE 32
+#define TIMEOUT 100 // Ticks to wait for each reply
+
+const char *usage = "usage: ping address [count]\n";
+
+// An ICMP echo request or reply header.
+struct icmp_echo {
+  uchar type;
+  uchar code;
+  ushort checksum;
+  ushort id;
+  ushort seq;
+};
+
+// The internet checksum of a buffer with an even length.
+ushort checksum(void *buf, int len) {
+  ushort *p = buf;
+  uint sum = 0;
+  for (; len > 1; len -= 2)
+    sum += *p++;
+  sum = (sum >> 16) + (sum & 0xffff);
+  sum += sum >> 16;
+  return ~sum;
+}
+
+// Send ICMP echo requests to a host through a raw socket and report the
+// replies, as a user space program would on any other system.
+int main(int argc, char *argv[]) {
+  if (argc < 2) {
+    printf(2, usage);
+    exit();
+  }
+  int count = argc > 2 ? atoi(argv[2]) : 4;
+
+  struct sockaddr_in addr;
+  memset(&addr, 0, sizeof(addr));
+  addr.sin_family = AF_INET;
+  addr.sin_addr.s_addr = inet_addr(argv[1]);
+
+  int sockfd = socket(AF_INET, SOCK_RAW, IPPROTO_ICMP);
+  if (sockfd < 0) {
+    printf(2, "ping: cannot open raw socket\n");
+    exit();
+  }
+  int timeout = TIMEOUT;
+  setsockopt(sockfd, SOL_SOCKET, SO_RCVTIMEO, &timeout, sizeof(timeout));
+
+  char request[sizeof(struct icmp_echo) + PAYLOAD_SIZE];
+  char reply[512];
+  int id = getpid();
+  for (int seq = 1; seq <= count; seq++) {
+    struct icmp_echo *echo = (struct icmp_echo *)request;
+    echo->type = ICMP_ECHO_REQUEST;
+    echo->code = 0;
+    echo->checksum = 0;
+    echo->id = htons(id);
+    echo->seq = htons(seq);
+    for (int i = 0; i < PAYLOAD_SIZE; i++)
+      request[sizeof(*echo) + i] = 'a' + i % 26;
+    echo->checksum = checksum(request, sizeof(request));
+
+    int start = uptime();
+    int n = sendto(sockfd, request, sizeof(request), 0, (struct sockaddr *)&addr,
+                   sizeof(addr));
+    if (n < 0) {
+      printf(2, "ping: send failed (%d)\n", -n);
+      break;
+    }
+
+    // Raw sockets receive every ICMP packet, skip any that are not our reply.
+    for (;;) {
+      n = recv(sockfd, reply, sizeof(reply), 0);
+      if (n < 0) {
+        printf(1, "seq=%d timeout\n", seq);
+        break;
+      }
+      int hlen = (reply[0] & 0xf) * 4;
+      if (n < hlen + sizeof(struct icmp_echo))
+        continue;
+      echo = (struct icmp_echo *)(reply + hlen);
+      if (echo->type != ICMP_ECHO_REPLY || ntohs(echo->id) != id ||
+          ntohs(echo->seq) != seq)
+        continue;
+      printf(1, "%d bytes from %s: seq=%d ttl=%d time=%d ticks\n", n - hlen,
+             argv[1], seq, (uchar)reply[8], uptime() - start);
+      break;
+    }
+    if (seq < count)
+      sleep(TIMEOUT);
+  }
+
+  close(sockfd);
+  exit();
+}
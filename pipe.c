@@ -7,6 +7,7 @@
 #include "spinlock.h"
 #include "sleeplock.h"
 #include "file.h"
+#include "poll.h"
 
 #define PIPESIZE 512
 
@@ -63,6 +64,7 @@ void pipeclose(struct pipe *p, int writable) {
     p->readopen = 0;
     wakeup(&p->nwrite);
   }
+  pollwakeup();
   if (p->readopen == 0 && p->writeopen == 0) {
     release(&p->lock);
     kfree((char *)p);
@@ -70,6 +72,27 @@ void pipeclose(struct pipe *p, int writable) {
     release(&p->lock);
 }
 
+// Report which poll events are ready on one end of the pipe.
+int pipepoll(struct pipe *p, int writable) {
+  int ev;
+
+  ev = 0;
+  acquire(&p->lock);
+  if (writable) {
+    if (p->readopen == 0)
+      ev |= POLLERR;
+    else if (p->nwrite != p->nread + PIPESIZE)
+      ev |= POLLOUT;
+  } else {
+    if (p->nread != p->nwrite)
+      ev |= POLLIN;
+    if (p->writeopen == 0)
+      ev |= POLLHUP;
+  }
+  release(&p->lock);
+  return ev;
+}
+
 // PAGEBREAK: 40
 int pipewrite(struct pipe *p, char *addr, int n) {
   int i;
@@ -87,6 +110,7 @@ int pipewrite(struct pipe *p, char *addr, int n) {
     p->data[p->nwrite++ % PIPESIZE] = addr[i];
   }
   wakeup(&p->nread); // DOC: pipewrite-wakeup1
+  pollwakeup();
   release(&p->lock);
   return n;
 }
@@ -108,6 +132,7 @@ int piperead(struct pipe *p, char *addr, int n) {
     addr[i] = p->data[p->nread++ % PIPESIZE];
   }
   wakeup(&p->nwrite); // DOC: piperead-wakeup
+  pollwakeup();
   release(&p->lock);
   return i;
 }
@@ -0,0 +1,11 @@
+#define POLLIN 0x001   // Data may be read without blocking
+#define POLLOUT 0x004  // Data may be written without blocking
+#define POLLERR 0x008  // Error condition (revents only)
+#define POLLHUP 0x010  // Hung up (revents only)
+#define POLLNVAL 0x020 // Invalid descriptor (revents only)
+
+struct pollfd {
+  int fd;        // File descriptor to poll, ignored if negative
+  short events;  // Requested events
+  short revents; // Returned events
+};
@@ -189,6 +189,7 @@ int fork(void) {
     if (curproc->ofile[i])
       np->ofile[i] = filedup(curproc->ofile[i]);
   np->cwd = idup(curproc->cwd);
+  sockfork(curproc->pid, np->pid);
 
   safestrcpy(np->name, curproc->name, sizeof(curproc->name));
 
@@ -222,6 +223,9 @@ void exit(void) {
     }
   }
 
+  // Release any sockets no longer owned by a running process.
+  sockexit(curproc->pid);
+
   begin_op();
   iput(curproc->cwd);
   end_op();
@@ -418,6 +422,34 @@ void sleep(void *chan, struct spinlock *lk) {
   }
 }
 
+// Atomically release a lock that is not a struct spinlock, such as
+// those used by the Rust network stack, and sleep on chan.
+// unlock(arg) is called with ptable.lock held, so no wakeup
+// can be missed. Unlike sleep, the caller must reacquire
+// its own lock when awakened.
+void sleepunlock(void *chan, void (*unlock)(void *), void *arg) {
+  struct proc *p = myproc();
+
+  if (p == 0)
+    panic("sleepunlock");
+
+  acquire(&ptable.lock);
+  unlock(arg);
+
+  // Go to sleep.
+  p->chan = chan;
+  p->state = SLEEPING;
+
+  sched();
+
+  // Tidy up.
+  p->chan = 0;
+  release(&ptable.lock);
+}
+
+// Has the current process been killed?
+int killed(void) { return myproc()->killed; }
+
 // PAGEBREAK!
 // Wake up all processes sleeping on chan.
 // The ptable lock must be held.
@@ -1,21 +1,41 @@
 use alloc::boxed::Box;
 use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
 
+use crate::error::NetError;
 use crate::ethernet::{EthernetAddress, EthernetFrame, Ethertype};
 use crate::ip::Ipv4Addr;
 use crate::net::NetworkDevice;
-use crate::packet_buffer::{FromBuffer, PacketBuffer, ToBuffer, BUFFER_SIZE};
+use crate::packet_buffer::{FromBuffer, PacketBuffer, ToBuffer};
+use crate::timer::{expired, now};
 
 const ARP_PACKET_SIZE: usize = 28;
 
+/// Ticks to wait for a reply before the first retry. The wait doubles after
+/// each retry.
+const ARP_RETRY_TICKS: u32 = 20;
+
+/// The number of requests sent before giving up on an address.
+const ARP_MAX_ATTEMPTS: u32 = 4;
+
+/// An address waiting on an ARP reply.
+struct Pending {
+    /// The number of requests sent so far.
+    attempts: u32,
+    /// The tick at which to retry, or give up.
+    deadline: u32,
+}
+
 pub struct ArpCache {
     cache: BTreeMap<Ipv4Addr, EthernetAddress>,
+    pending: BTreeMap<Ipv4Addr, Pending>,
 }
 
 impl ArpCache {
     pub const fn new() -> Self {
         ArpCache {
             cache: BTreeMap::new(),
+            pending: BTreeMap::new(),
         }
     }
 
@@ -30,16 +50,65 @@ impl ArpCache {
             Operation::Request | Operation::Unknown => return,
             Operation::Reply => {
                 self.cache.insert(arp_packet.spa, arp_packet.sha);
+                self.pending.remove(&arp_packet.spa);
             }
         }
     }
 
-    /// Send a request to resolve a hardware address.
-    pub fn resolve(protocol_address: &Ipv4Addr, device: &mut Box<dyn NetworkDevice>) {
-        let mut packet_buffer = PacketBuffer::new(BUFFER_SIZE);
+    /// Start resolving an address.
+    ///
+    /// Returns true if the caller should send the first request with
+    /// `ArpCache::resolve`, or false if a request is already outstanding.
+    pub fn request(&mut self, protocol_address: &Ipv4Addr) -> bool {
+        if self.pending.contains_key(protocol_address) {
+            return false;
+        }
+        self.pending.insert(
+            *protocol_address,
+            Pending {
+                attempts: 1,
+                deadline: now().wrapping_add(ARP_RETRY_TICKS),
+            },
+        );
+        true
+    }
+
+    /// Is a request for the address outstanding?
+    pub fn is_pending(&self, protocol_address: &Ipv4Addr) -> bool {
+        self.pending.contains_key(protocol_address)
+    }
 
-        let broadcast_hardware_address =
-            EthernetAddress::from_slice(&[0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]);
+    /// Advance outstanding requests on a clock tick.
+    ///
+    /// Returns the addresses that should be requested again, backing off
+    /// exponentially, and the addresses that have been given up on.
+    pub fn tick(&mut self) -> (Vec<Ipv4Addr>, Vec<Ipv4Addr>) {
+        let mut retry = Vec::new();
+        let mut failed = Vec::new();
+        for (address, pending) in self.pending.iter_mut() {
+            if !expired(pending.deadline) {
+                continue;
+            }
+            if pending.attempts >= ARP_MAX_ATTEMPTS {
+                failed.push(*address);
+                continue;
+            }
+            pending.deadline = now().wrapping_add(ARP_RETRY_TICKS << pending.attempts);
+            pending.attempts += 1;
+            retry.push(*address);
+        }
+        for address in failed.iter() {
+            self.pending.remove(address);
+        }
+        (retry, failed)
+    }
+
+    /// Send a request to resolve a hardware address.
+    pub fn resolve(
+        protocol_address: &Ipv4Addr,
+        device: &mut Box<dyn NetworkDevice>,
+    ) -> Result<(), NetError> {
+        let broadcast_hardware_address = EthernetAddress::BROADCAST;
         let arp_request = ArpPacket {
             htype: HardwareType::Ethernet,
             ptype: ProtocolType::Ipv4,
@@ -51,7 +120,7 @@ impl ArpCache {
             tha: broadcast_hardware_address,
             tpa: *protocol_address,
         };
-        packet_buffer.serialize(&arp_request);
+        let mut packet_buffer = PacketBuffer::from_payload(&arp_request);
 
         let ethernet_frame = EthernetFrame::new(
             broadcast_hardware_address,
@@ -60,7 +129,7 @@ impl ArpCache {
         );
         packet_buffer.serialize(&ethernet_frame);
 
-        device.send(packet_buffer);
+        device.send(packet_buffer)
     }
 }
 
@@ -179,7 +248,10 @@ impl ArpPacket {
 }
 
 impl FromBuffer for ArpPacket {
-    fn from_buffer(buf: &[u8]) -> Result<ArpPacket, ()> {
+    fn from_buffer(buf: &[u8]) -> Result<ArpPacket, NetError> {
+        if buf.len() < ARP_PACKET_SIZE {
+            return Err(NetError::Malformed);
+        }
         Ok(ArpPacket::from_slice(&buf))
     }
 
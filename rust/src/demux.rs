@@ -0,0 +1,192 @@
+use alloc::vec::Vec;
+
+use crate::ip::{Ipv4Addr, Protocol};
+
+/// The number of buckets in the demultiplexing table.
+const BUCKETS: usize = 64;
+
+/// A socket registered to receive packets.
+///
+/// Unspecified addresses and zero ports act as wildcards.
+#[derive(Debug, Copy, Clone)]
+pub struct Endpoint {
+    pub socket: usize,
+    pub local_address: Ipv4Addr,
+    pub local_port: u16,
+    pub remote_address: Ipv4Addr,
+    pub remote_port: u16,
+}
+
+impl Endpoint {
+    /// Score how specifically the endpoint matches a packet, or `None` if it
+    /// does not match at all.
+    ///
+    /// A connected endpoint only matches packets from its peer and an endpoint
+    /// bound to an address only matches packets sent to that address. The more
+    /// of the 4-tuple that was matched exactly, the higher the score.
+    fn matches(
+        &self,
+        local_address: Ipv4Addr,
+        remote_address: Ipv4Addr,
+        remote_port: u16,
+    ) -> Option<u32> {
+        let mut score = 0;
+        if !self.local_address.is_unspecified() {
+            if self.local_address != local_address {
+                return None;
+            }
+            score += 1;
+        }
+        if !self.remote_address.is_unspecified() {
+            if self.remote_address != remote_address {
+                return None;
+            }
+            score += 2;
+        }
+        if self.remote_port != 0 {
+            if self.remote_port != remote_port {
+                return None;
+            }
+            score += 2;
+        }
+        Some(score)
+    }
+}
+
+/// Maps the 4-tuple of an inbound packet to the socket it should be
+/// delivered to.
+///
+/// Endpoints are hashed on their protocol and local port, so every endpoint
+/// that could match a packet is found in a single bucket.
+pub struct DemuxTable {
+    buckets: [Vec<(Protocol, Endpoint)>; BUCKETS],
+    /// Raw sockets, by the protocol they receive. Raw sockets have no port to
+    /// hash on, and each of them sees every packet of its protocol.
+    raw: Vec<(Protocol, usize)>,
+    /// Packet sockets, by the Ethertype they receive, or `None` for those
+    /// receiving every Ethertype.
+    frames: Vec<(Option<u16>, usize)>,
+}
+
+impl DemuxTable {
+    pub const fn new() -> Self {
+        const EMPTY: Vec<(Protocol, Endpoint)> = Vec::new();
+        DemuxTable {
+            buckets: [EMPTY; BUCKETS],
+            raw: Vec::new(),
+            frames: Vec::new(),
+        }
+    }
+
+    fn bucket(protocol: Protocol, port: u16) -> usize {
+        // Fibonacci hash of the port, taking the well mixed upper bits.
+        let hash = (port as u32).wrapping_mul(0x9E37_79B1) >> 16;
+        (hash as usize ^ protocol.as_bytes() as usize) % BUCKETS
+    }
+
+    /// Register an endpoint, replacing any previous registration of the same
+    /// socket on the local port.
+    pub fn insert(&mut self, protocol: Protocol, endpoint: Endpoint) {
+        self.remove(protocol, endpoint.local_port, endpoint.socket);
+        self.buckets[Self::bucket(protocol, endpoint.local_port)].push((protocol, endpoint));
+    }
+
+    /// Remove the endpoint registered for a socket on a local port.
+    pub fn remove(&mut self, protocol: Protocol, local_port: u16, socket: usize) {
+        self.buckets[Self::bucket(protocol, local_port)]
+            .retain(|(p, x)| !(*p == protocol && x.socket == socket));
+    }
+
+    /// Find the socket an inbound packet should be delivered to.
+    ///
+    /// Returns the most specific matching endpoint. Ties go to the endpoint
+    /// registered first.
+    pub fn lookup(
+        &self,
+        protocol: Protocol,
+        local_address: Ipv4Addr,
+        local_port: u16,
+        remote_address: Ipv4Addr,
+        remote_port: u16,
+    ) -> Option<usize> {
+        let mut best: Option<(u32, usize)> = None;
+        for (p, endpoint) in self.buckets[Self::bucket(protocol, local_port)].iter() {
+            if *p != protocol || endpoint.local_port != local_port {
+                continue;
+            }
+            let score = match endpoint.matches(local_address, remote_address, remote_port) {
+                Some(x) => x,
+                None => continue,
+            };
+            if best.map_or(true, |(x, _)| score > x) {
+                best = Some((score, endpoint.socket));
+            }
+        }
+        best.map(|(_, socket)| socket)
+    }
+
+    /// Find every socket an inbound broadcast should be delivered to.
+    ///
+    /// Unlike `lookup`, every matching endpoint receives its own copy of a
+    /// broadcast, so sockets sharing a port with SO_REUSEADDR all see it.
+    pub fn lookup_all(
+        &self,
+        protocol: Protocol,
+        local_address: Ipv4Addr,
+        local_port: u16,
+        remote_address: Ipv4Addr,
+        remote_port: u16,
+    ) -> Vec<usize> {
+        self.buckets[Self::bucket(protocol, local_port)]
+            .iter()
+            .filter(|(p, x)| {
+                *p == protocol
+                    && x.local_port == local_port
+                    && x.matches(local_address, remote_address, remote_port)
+                        .is_some()
+            })
+            .map(|(_, x)| x.socket)
+            .collect()
+    }
+
+    /// Register a raw socket for the packets of a protocol.
+    pub fn insert_raw(&mut self, protocol: Protocol, socket: usize) {
+        self.raw.push((protocol, socket));
+    }
+
+    /// Remove the registration of a raw socket.
+    pub fn remove_raw(&mut self, socket: usize) {
+        self.raw.retain(|(_, x)| *x != socket);
+    }
+
+    /// Find every raw socket an inbound packet of a protocol should be
+    /// delivered to.
+    pub fn lookup_raw(&self, protocol: Protocol) -> Vec<usize> {
+        self.raw
+            .iter()
+            .filter(|(p, _)| *p == protocol)
+            .map(|(_, x)| *x)
+            .collect()
+    }
+
+    /// Register a packet socket for the frames of an Ethertype, or for every
+    /// frame if `ethertype` is `None`.
+    pub fn insert_frame(&mut self, ethertype: Option<u16>, socket: usize) {
+        self.frames.push((ethertype, socket));
+    }
+
+    /// Remove the registration of a packet socket.
+    pub fn remove_frame(&mut self, socket: usize) {
+        self.frames.retain(|(_, x)| *x != socket);
+    }
+
+    /// Find every packet socket an inbound frame of an Ethertype should be
+    /// delivered to.
+    pub fn lookup_frame(&self, ethertype: u16) -> Vec<usize> {
+        self.frames
+            .iter()
+            .filter(|(e, _)| e.map_or(true, |x| x == ethertype))
+            .map(|(_, x)| *x)
+            .collect()
+    }
+}
@@ -1,6 +1,7 @@
 use alloc::vec;
 use alloc::vec::Vec;
 
+use crate::error::NetError;
 use crate::ethernet::EthernetAddress;
 use crate::ip::Ipv4Addr;
 use crate::kernel::{ioapicenable, kalloc};
@@ -13,6 +14,10 @@ const IRQ_PIC0: u32 = 0xB;
 
 const EEPROM_DONE: u32 = 0x00000010;
 
+/// The number of registers in the multicast table array.
+const MTA_ENTRIES: usize =
+    (DeviceRegister::MTA_HIGH as usize - DeviceRegister::MTA_LOW as usize) / 4 + 1;
+
 // Device identifiers.
 const VENDOR_ID: u16 = 0x8086; // Intel.
 const DEVICE_ID: u16 = 0x100E; // 82540EM Gigabit Ethernet Controller.
@@ -42,8 +47,8 @@ enum DeviceRegister {
     _TPT = 0x040D4,
     RAL = 0x05400,
     RAH = 0x05404,
-    _MTA_LOW = 0x05200,
-    _MTA_HIGH = 0x053FC,
+    MTA_LOW = 0x05200,
+    MTA_HIGH = 0x053FC,
     _PBM_START = 0x10000,
 }
 
@@ -244,6 +249,10 @@ impl E1000 {
             }
             None => panic!("no mac address\n\x00"),
         }
+
+        // Receive no multicast frames until groups are joined.
+        self.write_multicast_table(&[0; MTA_ENTRIES]);
+
         // Allocate a recieve buffer for each of the descriptors.
         self.rx.resize_with(256, Default::default);
         for desc in self.rx.iter_mut() {
@@ -267,7 +276,6 @@ impl E1000 {
         rctl |= 1 << 1; // Receiver enable.
         rctl |= 1 << 2; // Store bad packets.
         rctl |= 1 << 3; // Receive all unicast packets.
-        rctl |= 1 << 4; // Receive all multicast packets.
         rctl |= 1 << 5; // Receivce long packets.
         rctl |= 1 << 15; // Accept broadcast packets.
         rctl |= 3 << 16; // Buffer size (4069 bytes).
@@ -335,6 +343,15 @@ impl E1000 {
     unsafe fn write_register(&self, r: DeviceRegister, data: u32) {
         core::ptr::write_volatile((self.mmio_base + r as u32) as *mut u32, data);
     }
+
+    /// Write the multicast table array, a bit for each hash of a multicast
+    /// address the device accepts frames for.
+    unsafe fn write_multicast_table(&self, table: &[u32; MTA_ENTRIES]) {
+        for (i, x) in table.iter().enumerate() {
+            let r = self.mmio_base + DeviceRegister::MTA_LOW as u32 + (i * 4) as u32;
+            core::ptr::write_volatile(r as *mut u32, *x);
+        }
+    }
 }
 
 /// Implement the common network interface.
@@ -383,7 +400,16 @@ impl NetworkDevice for E1000 {
     }
 
     /// Send the contents of a PacketBuffer over the wire.
-    fn send(&mut self, buf: PacketBuffer) {
+    ///
+    /// Fails with `NetError::NoBuffers` if the transmit ring is full.
+    fn send(&mut self, buf: PacketBuffer) -> Result<(), NetError> {
+        // The ring is full if advancing the tail would reach the head, which
+        // the device would read as an empty ring.
+        let next = (self.tx_idx as usize + 1) % self.tx.len();
+        if next as u32 == unsafe { self.read_register(DeviceRegister::TDH) } {
+            return Err(NetError::NoBuffers);
+        }
+
         let mut tx_desc = &mut self.tx[self.tx_idx as usize];
 
         // Write the payload into the transmit buffer.
@@ -405,6 +431,25 @@ impl NetworkDevice for E1000 {
         unsafe {
             self.write_register(DeviceRegister::TDT, self.tx_idx);
         }
+        Ok(())
+    }
+
+    /// Program the multicast table array with the hashes of `addresses`.
+    ///
+    /// The hash is bits 36 to 47 of the address, with the receive control
+    /// register's multicast offset left at zero. Addresses sharing a hash
+    /// with a joined group are accepted too, so the stack must still filter
+    /// what it receives.
+    ///
+    /// Reference: Manual - Section 13.5.1
+    fn set_multicast_filter(&mut self, addresses: &[EthernetAddress]) {
+        let mut table = [0u32; MTA_ENTRIES];
+        for address in addresses.iter() {
+            let bytes = address.as_bytes();
+            let hash = ((bytes[4] as usize) >> 4 | (bytes[5] as usize) << 4) & 0xFFF;
+            table[hash >> 5] |= 1 << (hash & 0x1F);
+        }
+        unsafe { self.write_multicast_table(&table) };
     }
 
     /// Read avaliable packets from the device.
@@ -0,0 +1,162 @@
+use alloc::collections::vec_deque::VecDeque;
+use alloc::vec::Vec;
+use core::ffi::c_void;
+
+use crate::error::NetError;
+
+// Epoll events, these must match the values in epoll.h.
+const EPOLLERR: u32 = 0x008;
+const EPOLLHUP: u32 = 0x010;
+const EPOLLET: u32 = 1 << 31;
+
+/// A socket registered with an epoll instance.
+#[derive(Debug)]
+struct Interest {
+    socket: u32,
+    /// The events to report, with EPOLLET for edge-triggered notification.
+    events: u32,
+    /// Returned with the events, for the caller to tell its sockets apart.
+    data: u32,
+    /// Is the socket in the ready list?
+    queued: bool,
+}
+
+/// A set of sockets a process waits on for events.
+///
+/// The network stack pushes a socket to the ready list of each instance it is
+/// registered with whenever it may have become ready, so waiting never scans
+/// the sockets that are idle. Whether a socket on the ready list really has
+/// events to report is checked as it is taken off the list. Level-triggered
+/// sockets go back on the list for as long as they have events, edge-triggered
+/// sockets only return once pushed again.
+pub struct Epoll {
+    interests: Vec<Interest>,
+    /// Sockets that may have events to report, oldest first.
+    ready: VecDeque<u32>,
+}
+
+impl Epoll {
+    /// Create an instance with no sockets registered.
+    pub fn new() -> Self {
+        Epoll {
+            interests: Vec::new(),
+            ready: VecDeque::new(),
+        }
+    }
+
+    /// Register a socket, which is checked for events straight away.
+    ///
+    /// Fails with `NetError::AlreadyExists` if the socket is already
+    /// registered.
+    pub fn add(&mut self, socket: u32, events: u32, data: u32) -> Result<(), NetError> {
+        if self.interests.iter().any(|x| x.socket == socket) {
+            return Err(NetError::AlreadyExists);
+        }
+        self.interests.push(Interest {
+            socket: socket,
+            events: events,
+            data: data,
+            queued: false,
+        });
+        self.notify(socket);
+        Ok(())
+    }
+
+    /// Change the events and data of a registered socket, which is checked for
+    /// events straight away.
+    ///
+    /// Fails with `NetError::NotFound` if the socket is not registered.
+    pub fn modify(&mut self, socket: u32, events: u32, data: u32) -> Result<(), NetError> {
+        let interest = self
+            .interests
+            .iter_mut()
+            .find(|x| x.socket == socket)
+            .ok_or(NetError::NotFound)?;
+        interest.events = events;
+        interest.data = data;
+        self.notify(socket);
+        Ok(())
+    }
+
+    /// Unregister a socket.
+    ///
+    /// Fails with `NetError::NotFound` if the socket is not registered.
+    pub fn remove(&mut self, socket: u32) -> Result<(), NetError> {
+        let index = self
+            .interests
+            .iter()
+            .position(|x| x.socket == socket)
+            .ok_or(NetError::NotFound)?;
+        if self.interests.swap_remove(index).queued {
+            self.ready.retain(|x| *x != socket);
+        }
+        Ok(())
+    }
+
+    /// Push a socket that may have become ready to the ready list.
+    pub fn notify(&mut self, socket: u32) {
+        if let Some(x) = self.interests.iter_mut().find(|x| x.socket == socket) {
+            if !x.queued {
+                x.queued = true;
+                self.ready.push_back(socket);
+            }
+        }
+    }
+
+    /// Take up to `max` sockets with events off the ready list, returning the
+    /// events and data of each.
+    ///
+    /// `poll` reports the events ready on a socket. Errors and hangups are
+    /// reported whether they were asked for or not.
+    pub fn collect<F: Fn(u32) -> u32>(&mut self, max: usize, poll: F) -> Vec<(u32, u32)> {
+        let mut events = Vec::new();
+        let mut again = Vec::new();
+        while events.len() < max {
+            let socket = match self.ready.pop_front() {
+                Some(x) => x,
+                None => break,
+            };
+            let interest = match self.interests.iter_mut().find(|x| x.socket == socket) {
+                Some(x) => x,
+                None => continue,
+            };
+            interest.queued = false;
+
+            let ready = poll(socket) & (interest.events | EPOLLERR | EPOLLHUP);
+            if ready == 0 {
+                continue;
+            }
+            events.push((ready, interest.data));
+
+            // Level-triggered sockets are checked again on the next call.
+            if interest.events & EPOLLET == 0 {
+                interest.queued = true;
+                again.push(socket);
+            }
+        }
+        self.ready.extend(again);
+        events
+    }
+
+    /// Does a socket on the ready list have events to report?
+    pub fn has_events<F: Fn(u32) -> u32>(&self, poll: F) -> bool {
+        self.ready.iter().any(|socket| {
+            self.interests
+                .iter()
+                .find(|x| x.socket == *socket)
+                .map_or(false, |x| {
+                    poll(*socket) & (x.events | EPOLLERR | EPOLLHUP) != 0
+                })
+        })
+    }
+
+    /// The registered sockets.
+    pub fn sockets(&self) -> impl Iterator<Item = u32> + '_ {
+        self.interests.iter().map(|x| x.socket)
+    }
+
+    /// The channel processes sleep on while waiting for events.
+    pub fn channel(&self) -> *const c_void {
+        self as *const Epoll as *const c_void
+    }
+}
@@ -0,0 +1,116 @@
+/// Errors returned by the network stack.
+///
+/// Network system calls return the negated errno style code of the error,
+/// these must match the values in user.h.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum NetError {
+    /// No unix domain socket path exists, or the socket is not registered
+    /// with the epoll instance (ENOENT).
+    NotFound,
+    /// The system call was interrupted by kill(...) (EINTR).
+    Interrupted,
+    /// The socket descriptor is invalid (EBADF).
+    BadSocket,
+    /// The process holds as many sockets as it may (EMFILE).
+    TooManySockets,
+    /// The system holds as many sockets as it may (ENFILE).
+    SocketTableFull,
+    /// The operation would block on a non-blocking socket (EAGAIN).
+    WouldBlock,
+    /// Sending to the broadcast address without SO_BROADCAST (EACCES).
+    AccessDenied,
+    /// A user pointer is invalid (EFAULT).
+    Fault,
+    /// The socket is already registered with the epoll instance (EEXIST).
+    AlreadyExists,
+    /// An argument is invalid (EINVAL).
+    InvalidArgument,
+    /// The socket, or the remote of a stream socket, has been shut down
+    /// (EPIPE).
+    Shutdown,
+    /// A packet could not be parsed (EBADMSG).
+    Malformed,
+    /// The datagram is too large to send (EMSGSIZE).
+    MessageTooLong,
+    /// The socket option is not supported (ENOPROTOOPT).
+    UnknownOption,
+    /// The combination of domain, type and protocol is not supported
+    /// (EPROTONOSUPPORT).
+    ProtocolNotSupported,
+    /// The operation is not supported by the socket (EOPNOTSUPP).
+    OperationNotSupported,
+    /// The address family does not match the socket (EAFNOSUPPORT).
+    AddressFamilyNotSupported,
+    /// The local port is already bound (EADDRINUSE).
+    AddressInUse,
+    /// No ephemeral ports are free (EADDRNOTAVAIL).
+    AddressNotAvailable,
+    /// There is no network device (ENETDOWN).
+    NetworkDown,
+    /// The network device has no free transmit buffers (ENOBUFS).
+    NoBuffers,
+    /// The socket is already connected (EISCONN).
+    IsConnected,
+    /// The socket is not connected (ENOTCONN).
+    NotConnected,
+    /// The operation timed out (ETIMEDOUT).
+    TimedOut,
+    /// The remote port is not listening (ECONNREFUSED).
+    ConnectionRefused,
+    /// The remote address did not resolve (EHOSTUNREACH).
+    HostUnreachable,
+    /// A connect(...) is already in progress (EALREADY).
+    Already,
+    /// A non-blocking connect(...) is still in progress (EINPROGRESS).
+    InProgress,
+}
+
+impl NetError {
+    /// The errno style code of the error.
+    pub fn code(&self) -> i32 {
+        match self {
+            NetError::NotFound => 2,
+            NetError::Interrupted => 4,
+            NetError::BadSocket => 9,
+            NetError::TooManySockets => 24,
+            NetError::SocketTableFull => 23,
+            NetError::WouldBlock => 11,
+            NetError::AccessDenied => 13,
+            NetError::Fault => 14,
+            NetError::AlreadyExists => 17,
+            NetError::InvalidArgument => 22,
+            NetError::Shutdown => 32,
+            NetError::Malformed => 74,
+            NetError::MessageTooLong => 90,
+            NetError::UnknownOption => 92,
+            NetError::ProtocolNotSupported => 93,
+            NetError::OperationNotSupported => 95,
+            NetError::AddressFamilyNotSupported => 97,
+            NetError::AddressInUse => 98,
+            NetError::AddressNotAvailable => 99,
+            NetError::NetworkDown => 100,
+            NetError::NoBuffers => 105,
+            NetError::IsConnected => 106,
+            NetError::NotConnected => 107,
+            NetError::TimedOut => 110,
+            NetError::ConnectionRefused => 111,
+            NetError::HostUnreachable => 113,
+            NetError::Already => 114,
+            NetError::InProgress => 115,
+        }
+    }
+
+    /// The value returned from a system call failing with this error.
+    pub fn to_syscall(&self) -> i32 {
+        -self.code()
+    }
+}
+
+/// The value returned from a system call, the result on success or the
+/// negated error code on failure.
+pub fn syscall_return(result: Result<u32, NetError>) -> i32 {
+    match result {
+        Ok(x) => x as i32,
+        Err(x) => x.to_syscall(),
+    }
+}
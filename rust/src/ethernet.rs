@@ -1,3 +1,5 @@
+use crate::error::NetError;
+use crate::ip::Ipv4Addr;
 use crate::packet_buffer::{FromBuffer, ToBuffer};
 
 /// An ethernet (MAC) address.
@@ -5,10 +7,23 @@ use crate::packet_buffer::{FromBuffer, ToBuffer};
 pub struct EthernetAddress([u8; 6]);
 
 impl EthernetAddress {
+    /// The broadcast address, ff:ff:ff:ff:ff:ff.
+    pub const BROADCAST: EthernetAddress = EthernetAddress([0xFF; 6]);
+
     pub fn from_slice(buf: &[u8]) -> EthernetAddress {
         EthernetAddress([buf[0], buf[1], buf[2], buf[3], buf[4], buf[5]])
     }
 
+    /// The address frames sent to an IPv4 multicast group are sent to, the
+    /// low 23 bits of the group following 01:00:5e.
+    ///
+    /// RFC1112 Section 6.4
+    /// https://tools.ietf.org/html/rfc1112
+    pub fn from_multicast(group: Ipv4Addr) -> EthernetAddress {
+        let group = group.as_bytes();
+        EthernetAddress([0x01, 0x00, 0x5E, group[1] & 0x7F, group[2], group[3]])
+    }
+
     pub fn as_bytes(&self) -> [u8; 6] {
         self.0
     }
@@ -89,7 +104,10 @@ impl EthernetFrame {
 }
 
 impl FromBuffer for EthernetFrame {
-    fn from_buffer(buf: &[u8]) -> Result<EthernetFrame, ()> {
+    fn from_buffer(buf: &[u8]) -> Result<EthernetFrame, NetError> {
+        if buf.len() < 14 {
+            return Err(NetError::Malformed);
+        }
         Ok(EthernetFrame::from_slice(&buf))
     }
 
@@ -0,0 +1,246 @@
+use alloc::vec::Vec;
+use core::slice;
+
+use crate::mm::PAGE_SIZE;
+use crate::packet_buffer::ToBuffer;
+
+/// A range of bytes spread over several buffers, read as if the buffers were
+/// one.
+///
+/// The kernel allocator hands out at most a page at a time, so data larger
+/// than a page, such as the iovecs of sendmsg(...) or a large datagram, is
+/// passed through the stack as a gather rather than copied into a single
+/// buffer.
+pub struct Gather<'a, T> {
+    buffers: &'a [T],
+    /// The offset of the range into the buffers.
+    start: usize,
+    len: usize,
+}
+
+// Derived implementations would require the buffers to be `Copy`.
+impl<'a, T> Clone for Gather<'a, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'a, T> Copy for Gather<'a, T> {}
+
+impl<'a, T: AsRef<[u8]>> Gather<'a, T> {
+    /// Gather all the bytes of `buffers`.
+    pub fn new(buffers: &'a [T]) -> Self {
+        Gather {
+            buffers: buffers,
+            start: 0,
+            len: buffers.iter().map(|x| x.as_ref().len()).sum(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The bytes from `start` up to `end` of the range, clamped to the range.
+    pub fn range(&self, start: usize, end: usize) -> Gather<'a, T> {
+        let end = end.min(self.len);
+        let start = start.min(end);
+        Gather {
+            buffers: self.buffers,
+            start: self.start + start,
+            len: end - start,
+        }
+    }
+
+    /// The part of each buffer that falls in the range, in order.
+    fn pieces(&self) -> impl Iterator<Item = &'a [u8]> {
+        let mut skip = self.start;
+        let mut left = self.len;
+        self.buffers.iter().filter_map(move |x| {
+            let x = x.as_ref();
+            if skip >= x.len() {
+                skip -= x.len();
+                return None;
+            }
+            let piece = &x[skip..];
+            let piece = &piece[..piece.len().min(left)];
+            skip = 0;
+            left -= piece.len();
+            match piece.len() {
+                0 => None,
+                _ => Some(piece),
+            }
+        })
+    }
+
+    /// Copy the range to the start of `buf`, which must be large enough to
+    /// hold it.
+    pub fn copy_to(&self, buf: &mut [u8]) {
+        let mut offset = 0;
+        for piece in self.pieces() {
+            buf[offset..offset + piece.len()].copy_from_slice(piece);
+            offset += piece.len();
+        }
+    }
+
+    /// Copy as much of the range as fits to `out`, starting `offset` bytes into
+    /// its buffers as if they were one. Returns the number of bytes copied.
+    pub fn scatter(&self, out: &mut [&mut [u8]], offset: usize) -> usize {
+        let mut skip = offset;
+        let mut copied = 0;
+        for buf in out.iter_mut() {
+            if skip >= buf.len() {
+                skip -= buf.len();
+                continue;
+            }
+            let size = (buf.len() - skip).min(self.len - copied);
+            self.range(copied, copied + size)
+                .copy_to(&mut buf[skip..skip + size]);
+            skip = 0;
+            copied += size;
+            if copied == self.len {
+                break;
+            }
+        }
+        copied
+    }
+
+    /// Copy the range into a new buffer, which must fit in a page.
+    pub fn to_vec(&self) -> Vec<u8> {
+        let mut buf = alloc::vec![0u8; self.len];
+        self.copy_to(&mut buf);
+        buf
+    }
+}
+
+impl<'a, T: AsRef<[u8]>> ToBuffer for Gather<'a, T> {
+    fn to_buffer(&self, buf: &mut [u8]) {
+        self.copy_to(buf);
+    }
+
+    fn size(&self) -> usize {
+        self.len
+    }
+}
+
+/// Bytes held in chunks of at most a page, so more than a page can be queued
+/// on a socket without a single large allocation.
+#[derive(Debug)]
+pub struct Chunks {
+    /// The bytes, if they fit in a page, which saves allocating a list for the
+    /// common case of a single chunk.
+    single: Vec<u8>,
+    /// The chunks of bytes larger than a page, empty otherwise.
+    chunks: Vec<Vec<u8>>,
+}
+
+impl Chunks {
+    /// Copy the bytes of `data` into as many chunks as they need.
+    pub fn from_gather<T: AsRef<[u8]>>(data: Gather<'_, T>) -> Chunks {
+        if data.len() <= PAGE_SIZE {
+            return Chunks {
+                single: data.to_vec(),
+                chunks: Vec::new(),
+            };
+        }
+
+        let mut chunks = Vec::new();
+        let mut start = 0;
+        while start < data.len() {
+            let end = data.len().min(start + PAGE_SIZE);
+            chunks.push(data.range(start, end).to_vec());
+            start = end;
+        }
+        Chunks {
+            single: Vec::new(),
+            chunks: chunks,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.gather().len()
+    }
+
+    /// The bytes of the chunks, in order.
+    pub fn gather(&self) -> Gather<'_, Vec<u8>> {
+        match self.chunks.is_empty() {
+            true => Gather::new(slice::from_ref(&self.single)),
+            false => Gather::new(&self.chunks),
+        }
+    }
+
+    /// Remove the first `len` bytes.
+    pub fn consume(&mut self, len: usize) {
+        if self.chunks.is_empty() {
+            self.single.drain(..len.min(self.single.len()));
+            return;
+        }
+
+        let mut left = len;
+        while left > 0 && !self.chunks.is_empty() {
+            if left < self.chunks[0].len() {
+                self.chunks[0].drain(..left);
+                return;
+            }
+            left -= self.chunks.remove(0).len();
+        }
+    }
+}
+
+impl From<&[u8]> for Chunks {
+    fn from(data: &[u8]) -> Chunks {
+        Chunks::from_gather(Gather::new(&[data]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn test_range() {
+        let buffers: [&[u8]; 3] = [b"abc", b"", b"defgh"];
+        let data = Gather::new(&buffers);
+        assert_eq!(data.len(), 8);
+        assert_eq!(data.to_vec(), b"abcdefgh");
+        assert_eq!(data.range(2, 5).to_vec(), b"cde");
+        assert_eq!(data.range(2, 5).range(1, 10).to_vec(), b"de");
+        assert!(data.range(6, 3).is_empty());
+    }
+
+    #[test]
+    fn test_scatter() {
+        let buffers: [&[u8]; 2] = [b"abc", b"defgh"];
+        let data = Gather::new(&buffers);
+
+        let mut first = [0u8; 2];
+        let mut second = [0u8; 4];
+        let mut out: [&mut [u8]; 2] = [&mut first, &mut second];
+        assert_eq!(data.range(0, 3).scatter(&mut out, 1), 3);
+        assert_eq!(data.scatter(&mut out, 4), 2);
+        assert_eq!(first, *b"\0a");
+        assert_eq!(second, *b"bcab");
+    }
+
+    #[test]
+    fn test_chunks() {
+        let data = vec![7u8; PAGE_SIZE * 2 + 10];
+        let mut chunks = Chunks::from(&data[..]);
+        assert_eq!(chunks.len(), data.len());
+        assert_eq!(chunks.gather().range(PAGE_SIZE - 1, PAGE_SIZE + 1).len(), 2);
+
+        chunks.consume(PAGE_SIZE + 5);
+        assert_eq!(chunks.len(), PAGE_SIZE + 5);
+        chunks.consume(PAGE_SIZE + 5);
+        assert_eq!(chunks.len(), 0);
+
+        let mut chunks = Chunks::from(&b"hello"[..]);
+        chunks.consume(2);
+        assert_eq!(chunks.gather().to_vec(), b"llo");
+    }
+}
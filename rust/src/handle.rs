@@ -0,0 +1,141 @@
+use alloc::vec::Vec;
+
+/// The bits of a handle holding the slot index, the remaining bits hold the
+/// generation of the slot.
+const INDEX_BITS: u32 = 16;
+const INDEX_MASK: usize = (1 << INDEX_BITS) - 1;
+
+/// The generation is kept to 15 bits so handles stay positive when passed to
+/// C as an `int`.
+const GENERATION_MASK: u32 = 0x7FFF;
+
+/// A slot in the table, holding a value or waiting to be reused.
+struct Slot<T> {
+    /// Incremented each time the slot is freed, so handles to earlier values
+    /// no longer match.
+    generation: u32,
+    value: Option<T>,
+}
+
+/// A table of values addressed by generation-counted handles.
+///
+/// A handle packs the index of a slot with the generation of the slot when the
+/// value was inserted. Slots are reused once their value is removed, but the
+/// generation moves on, so a stale handle to a removed value never reaches
+/// the value now in its slot.
+pub struct HandleTable<T> {
+    slots: Vec<Slot<T>>,
+    /// The indices of empty slots, most recently freed last.
+    free: Vec<usize>,
+    /// The most values the table holds at once.
+    capacity: usize,
+    /// The number of values in the table.
+    len: usize,
+}
+
+impl<T> HandleTable<T> {
+    /// Create a new table holding at most `capacity` values.
+    pub const fn new(capacity: usize) -> Self {
+        HandleTable {
+            slots: Vec::new(),
+            free: Vec::new(),
+            capacity: capacity,
+            len: 0,
+        }
+    }
+
+    /// Is the table holding as many values as it can?
+    pub fn is_full(&self) -> bool {
+        self.len >= self.capacity.min(INDEX_MASK + 1)
+    }
+
+    /// Insert a value, returning its handle, or `None` if the table is full.
+    pub fn insert(&mut self, value: T) -> Option<usize> {
+        if self.is_full() {
+            return None;
+        }
+
+        let index = match self.free.pop() {
+            Some(x) => x,
+            None => {
+                self.slots.push(Slot {
+                    generation: 0,
+                    value: None,
+                });
+                self.slots.len() - 1
+            }
+        };
+        let slot = &mut self.slots[index];
+        slot.value = Some(value);
+        self.len += 1;
+        Some(HandleTable::<T>::handle(index, slot.generation))
+    }
+
+    /// Look up the value of a handle.
+    pub fn get(&self, handle: usize) -> Option<&T> {
+        let index = self.index(handle)?;
+        self.slots[index].value.as_ref()
+    }
+
+    /// Look up the value of a handle for modification.
+    pub fn get_mut(&mut self, handle: usize) -> Option<&mut T> {
+        let index = self.index(handle)?;
+        self.slots[index].value.as_mut()
+    }
+
+    /// Remove the value of a handle, freeing its slot for reuse.
+    pub fn remove(&mut self, handle: usize) -> Option<T> {
+        let index = self.index(handle)?;
+        let slot = &mut self.slots[index];
+        let value = slot.value.take()?;
+        slot.generation = (slot.generation + 1) & GENERATION_MASK;
+        self.free.push(index);
+        self.len -= 1;
+        Some(value)
+    }
+
+    /// Iterate over the values in the table.
+    pub fn values(&self) -> impl Iterator<Item = &T> {
+        self.slots.iter().filter_map(|x| x.value.as_ref())
+    }
+
+    /// Iterate over the values in the table for modification.
+    pub fn values_mut(&mut self) -> impl Iterator<Item = &mut T> {
+        self.slots.iter_mut().filter_map(|x| x.value.as_mut())
+    }
+
+    /// Iterate over the handles and values in the table.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &T)> {
+        self.slots.iter().enumerate().filter_map(|(index, slot)| {
+            let handle = HandleTable::<T>::handle(index, slot.generation);
+            slot.value.as_ref().map(|x| (handle, x))
+        })
+    }
+
+    /// Iterate over the handles and values in the table for modification.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (usize, &mut T)> {
+        self.slots
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(index, slot)| {
+                let handle = HandleTable::<T>::handle(index, slot.generation);
+                slot.value.as_mut().map(|x| (handle, x))
+            })
+    }
+
+    /// Pack a slot index and generation into a handle.
+    fn handle(index: usize, generation: u32) -> usize {
+        (generation as usize) << INDEX_BITS | index
+    }
+
+    /// The slot index of a handle, or `None` if the handle is out of range or
+    /// from an earlier generation of its slot.
+    fn index(&self, handle: usize) -> Option<usize> {
+        let index = handle & INDEX_MASK;
+        let generation = (handle >> INDEX_BITS) as u32;
+        match self.slots.get(index) {
+            Some(x) if x.generation == generation => Some(index),
+            _ => None,
+        }
+    }
+}
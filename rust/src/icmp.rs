@@ -1,5 +1,6 @@
 use alloc::vec::Vec;
 
+use crate::error::NetError;
 use crate::packet_buffer::{FromBuffer, ToBuffer};
 
 /// Represents an ICMP echo packet.
@@ -27,10 +28,22 @@ impl IcmpEchoMessage {
     }
 }
 
+/// Represents an ICMP destination unreachable message.
+#[derive(Debug, Clone)]
+pub struct IcmpUnreachableMessage {
+    pub code: u8,
+    /// The IP header and leading bytes of the undeliverable datagram.
+    pub data: Vec<u8>,
+}
+
+/// The destination unreachable code sent when no socket is bound to the port.
+pub const PORT_UNREACHABLE: u8 = 3;
+
 /// Represents an ICMP packet.
 #[derive(Debug, Clone)]
 pub enum IcmpPacket {
     EchoMessage(IcmpEchoMessage),
+    UnreachableMessage(IcmpUnreachableMessage),
 }
 
 #[derive(Debug, Copy, Clone, PartialEq)]
@@ -83,25 +96,37 @@ impl Type {
 }
 
 impl IcmpPacket {
-    pub fn from_slice(buf: &[u8]) -> IcmpPacket {
+    pub fn from_slice(buf: &[u8]) -> Result<IcmpPacket, NetError> {
+        if buf.len() < 8 {
+            return Err(NetError::Malformed);
+        }
+
         let r#type = Type::from_slice(&buf[0..]);
         match r#type {
-            Type::EchoReply | Type::EchoRequest => IcmpPacket::EchoMessage(IcmpEchoMessage {
+            Type::EchoReply | Type::EchoRequest => Ok(IcmpPacket::EchoMessage(IcmpEchoMessage {
                 r#type: r#type,
                 code: buf[1],
                 checksum: u16::from_be_bytes([buf[2], buf[3]]),
                 identifier: u16::from_be_bytes([buf[4], buf[5]]),
                 sequence_number: u16::from_be_bytes([buf[6], buf[7]]),
                 data: buf[8..].to_vec(),
-            }),
-            _ => panic!("unknown icmp type\n\x00"),
+            })),
+            Type::DestinationUnreachable => {
+                Ok(IcmpPacket::UnreachableMessage(IcmpUnreachableMessage {
+                    code: buf[1],
+                    data: buf[8..].to_vec(),
+                }))
+            }
+            // Messages we do not handle are dropped.
+            _ => Err(NetError::Malformed),
         }
     }
 
     fn calculate_checksum(buf: &[u8]) -> u16 {
         let mut sum = 0u32;
-        for i in (0..buf.len()).step_by(2) {
-            let value = u16::from_be_bytes([buf[i], buf[i + 1]]);
+        // An odd trailing byte is padded with zero.
+        for chunk in buf.chunks(2) {
+            let value = u16::from_be_bytes([chunk[0], *chunk.get(1).unwrap_or(&0)]);
             sum += value as u32;
         }
 
@@ -112,14 +137,14 @@ impl IcmpPacket {
 }
 
 impl FromBuffer for IcmpPacket {
-    fn from_buffer(buf: &[u8]) -> Result<IcmpPacket, ()> {
-        Ok(IcmpPacket::from_slice(&buf))
+    fn from_buffer(buf: &[u8]) -> Result<IcmpPacket, NetError> {
+        IcmpPacket::from_slice(&buf)
     }
 
     fn size(&self) -> usize {
         match self {
             IcmpPacket::EchoMessage(x) => 8 + x.data.len(),
-            _ => panic!("unknown icmp type\n\x00"),
+            IcmpPacket::UnreachableMessage(x) => 8 + x.data.len(),
         }
     }
 }
@@ -138,14 +163,22 @@ impl ToBuffer for IcmpPacket {
                 let checksum = IcmpPacket::calculate_checksum(&buf[0..8 + x.data.len()]);
                 buf[2..4].copy_from_slice(&(checksum.to_be_bytes()));
             }
-            _ => panic!("unknown icmp type\n\x00"),
+            IcmpPacket::UnreachableMessage(x) => {
+                buf[0..1].copy_from_slice(&[Type::DestinationUnreachable.as_bytes()]);
+                buf[1..2].copy_from_slice(&[x.code]);
+                buf[2..8].copy_from_slice(&[0u8; 6]);
+                buf[8..8 + x.data.len()].copy_from_slice(&x.data[..]);
+
+                let checksum = IcmpPacket::calculate_checksum(&buf[0..8 + x.data.len()]);
+                buf[2..4].copy_from_slice(&(checksum.to_be_bytes()));
+            }
         }
     }
 
     fn size(&self) -> usize {
         match self {
             IcmpPacket::EchoMessage(x) => 8 + x.data.len(),
-            _ => panic!("unknown icmp type\n\x00"),
+            IcmpPacket::UnreachableMessage(x) => 8 + x.data.len(),
         }
     }
 }
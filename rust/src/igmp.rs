@@ -0,0 +1,345 @@
+use alloc::vec::Vec;
+
+use crate::error::NetError;
+use crate::ethernet::EthernetAddress;
+use crate::ip::Ipv4Addr;
+use crate::packet_buffer::{FromBuffer, ToBuffer};
+use crate::timer::{expired, now};
+
+/// The all-hosts group, 224.0.0.1, which every host belongs to and general
+/// queries are sent to.
+const ALL_HOSTS_GROUP: u32 = 0xE0000001;
+
+/// The all-routers group, 224.0.0.2, which leave messages are sent to.
+pub const ALL_ROUTERS_GROUP: u32 = 0xE0000002;
+
+/// Clock ticks per tenth of a second, the unit of the maximum response time
+/// of a query.
+const TICKS_PER_DECISECOND: u32 = 10;
+
+/// The maximum response time of an IGMPv1 query, which leaves it zero.
+const V1_MAX_RESPONSE_TIME: u8 = 100;
+
+/// The number of reports sent when joining a group, in case the first is
+/// lost.
+const UNSOLICITED_REPORTS: u8 = 2;
+
+/// The ticks between unsolicited reports, 10 seconds.
+const UNSOLICITED_REPORT_INTERVAL: u32 = 1000;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Type {
+    MembershipQuery,
+    V1MembershipReport,
+    V2MembershipReport,
+    LeaveGroup,
+}
+
+impl Type {
+    pub fn from_slice(buf: &[u8]) -> Result<Type, NetError> {
+        match buf[0] {
+            0x11u8 => Ok(Type::MembershipQuery),
+            0x12u8 => Ok(Type::V1MembershipReport),
+            0x16u8 => Ok(Type::V2MembershipReport),
+            0x17u8 => Ok(Type::LeaveGroup),
+            // Messages we do not handle, such as IGMPv3 reports, are dropped.
+            _ => Err(NetError::Malformed),
+        }
+    }
+
+    pub fn as_bytes(&self) -> u8 {
+        match self {
+            Type::MembershipQuery => 0x11u8,
+            Type::V1MembershipReport => 0x12u8,
+            Type::V2MembershipReport => 0x16u8,
+            Type::LeaveGroup => 0x17u8,
+        }
+    }
+}
+
+/// An IGMPv2 message.
+///
+/// RFC2236 Section 2
+/// https://tools.ietf.org/html/rfc2236
+#[derive(Debug, Clone)]
+pub struct IgmpPacket {
+    pub r#type: Type,
+    /// The most tenths of a second a host may wait before answering a query.
+    pub max_response_time: u8,
+    /// The group the message is about, unspecified for a general query.
+    pub group: Ipv4Addr,
+}
+
+impl IgmpPacket {
+    pub fn new(r#type: Type, group: Ipv4Addr) -> IgmpPacket {
+        IgmpPacket {
+            r#type: r#type,
+            max_response_time: 0,
+            group: group,
+        }
+    }
+
+    /// Parse an IGMP message, `buf` holding exactly the payload of its IPv4
+    /// packet.
+    ///
+    /// The checksum covers the whole message. Longer messages, such as IGMPv3
+    /// queries, are accepted and the bytes past the first 8 ignored.
+    fn from_slice(buf: &[u8]) -> Result<IgmpPacket, NetError> {
+        if buf.len() < 8 || IgmpPacket::calculate_checksum(buf) != 0 {
+            return Err(NetError::Malformed);
+        }
+
+        Ok(IgmpPacket {
+            r#type: Type::from_slice(&buf[0..])?,
+            max_response_time: buf[1],
+            group: Ipv4Addr::from_slice(&buf[4..8]),
+        })
+    }
+
+    fn calculate_checksum(buf: &[u8]) -> u16 {
+        let mut sum = 0u32;
+        // An odd trailing byte is padded with zero.
+        for chunk in buf.chunks(2) {
+            sum += u16::from_be_bytes([chunk[0], *chunk.get(1).unwrap_or(&0)]) as u32;
+        }
+
+        let check = (sum >> 16) + (sum & 0xffff);
+        let check = (check >> 16) + check;
+        !(check as u16)
+    }
+}
+
+impl FromBuffer for IgmpPacket {
+    fn from_buffer(buf: &[u8]) -> Result<IgmpPacket, NetError> {
+        IgmpPacket::from_slice(&buf)
+    }
+
+    fn size(&self) -> usize {
+        8
+    }
+}
+
+impl ToBuffer for IgmpPacket {
+    fn to_buffer(&self, buf: &mut [u8]) {
+        buf[0] = self.r#type.as_bytes();
+        buf[1] = self.max_response_time;
+        buf[2..4].copy_from_slice(&0u16.to_be_bytes());
+        buf[4..8].copy_from_slice(&self.group.as_bytes());
+
+        let checksum = IgmpPacket::calculate_checksum(&buf[0..8]);
+        buf[2..4].copy_from_slice(&checksum.to_be_bytes());
+    }
+
+    fn size(&self) -> usize {
+        8
+    }
+}
+
+/// A multicast group joined by one or more sockets.
+#[derive(Debug)]
+struct Group {
+    address: Ipv4Addr,
+    /// The number of sockets that joined the group, 0 once it is left.
+    members: usize,
+    /// When the next membership report is due, if one is.
+    report_at: Option<u32>,
+    /// Unsolicited reports still to send after joining the group.
+    unsolicited: u8,
+}
+
+/// The multicast groups the host belongs to, and the IGMP reports due for
+/// them.
+///
+/// Every host belongs to the all-hosts group, so the filter always includes
+/// it, whether or not a socket has joined it.
+pub struct GroupTable {
+    groups: Vec<Group>,
+    /// Groups have been joined or left since the multicast filter of the
+    /// device was last programmed.
+    changed: bool,
+}
+
+impl GroupTable {
+    /// Create an empty table, whose filter still has to be programmed with the
+    /// all-hosts group.
+    pub const fn new() -> Self {
+        GroupTable {
+            groups: Vec::new(),
+            changed: true,
+        }
+    }
+
+    /// Add a socket to a group, reporting the membership straight away if it
+    /// is the first.
+    pub fn join(&mut self, group: Ipv4Addr) {
+        match self.groups.iter_mut().find(|x| x.address == group) {
+            Some(x) if x.members > 0 => x.members += 1,
+            // Rejoined before the leave was sent.
+            Some(x) => {
+                x.members = 1;
+                x.report_at = Some(now());
+                x.unsolicited = UNSOLICITED_REPORTS;
+            }
+            None => {
+                self.groups.push(Group {
+                    address: group,
+                    members: 1,
+                    report_at: Some(now()),
+                    unsolicited: UNSOLICITED_REPORTS,
+                });
+                self.changed = true;
+            }
+        }
+    }
+
+    /// Remove a socket from a group. The group is left once its last member
+    /// is removed.
+    pub fn leave(&mut self, group: Ipv4Addr) {
+        if let Some(x) = self.groups.iter_mut().find(|x| x.address == group) {
+            x.members = x.members.saturating_sub(1);
+        }
+    }
+
+    /// Schedule reports in answer to a query for `group`, or for every group
+    /// if it is unspecified.
+    ///
+    /// Each report is delayed by a random part of the maximum response time,
+    /// so the hosts on the network do not all answer at once. A report that
+    /// is already due sooner is left alone.
+    pub fn query(&mut self, group: Ipv4Addr, max_response_time: u8) {
+        let max_response_time = match max_response_time {
+            0 => V1_MAX_RESPONSE_TIME,
+            x => x,
+        };
+        let max_delay = max_response_time as u32 * TICKS_PER_DECISECOND;
+
+        for x in self.groups.iter_mut() {
+            if x.members == 0 || !(group.is_unspecified() || group == x.address) {
+                continue;
+            }
+
+            let report_at = now().wrapping_add(random_delay(x.address, max_delay));
+            match x.report_at {
+                Some(y) if (y.wrapping_sub(report_at) as i32) <= 0 => (),
+                _ => x.report_at = Some(report_at),
+            }
+        }
+    }
+
+    /// Another host reported membership of `group`, so the report due from
+    /// this host is suppressed.
+    pub fn report_heard(&mut self, group: Ipv4Addr) {
+        if let Some(x) = self.groups.iter_mut().find(|x| x.address == group) {
+            x.report_at = None;
+            x.unsolicited = 0;
+        }
+    }
+
+    /// Collect the IGMP messages due.
+    ///
+    /// Returns the groups to report membership of and the groups to leave.
+    /// Left groups are forgotten. Membership of the all-hosts group is never
+    /// reported or left.
+    pub fn tick(&mut self) -> (Vec<Ipv4Addr>, Vec<Ipv4Addr>) {
+        let all_hosts = Ipv4Addr::from(ALL_HOSTS_GROUP);
+        let mut reports = Vec::new();
+        let mut leaves = Vec::new();
+        for x in self.groups.iter_mut() {
+            if x.members == 0 {
+                if x.address != all_hosts {
+                    leaves.push(x.address);
+                }
+                continue;
+            }
+
+            match x.report_at {
+                Some(y) if expired(y) => {
+                    if x.address != all_hosts {
+                        reports.push(x.address);
+                    }
+                    x.unsolicited = x.unsolicited.saturating_sub(1);
+                    x.report_at = match x.unsolicited {
+                        0 => None,
+                        _ => Some(now().wrapping_add(UNSOLICITED_REPORT_INTERVAL)),
+                    };
+                }
+                _ => (),
+            }
+        }
+
+        let len = self.groups.len();
+        self.groups.retain(|x| x.members > 0);
+        if self.groups.len() != len {
+            self.changed = true;
+        }
+        (reports, leaves)
+    }
+
+    /// The hardware addresses the device should receive multicast frames on,
+    /// or `None` if they have not changed since last asked.
+    pub fn filter(&mut self) -> Option<Vec<EthernetAddress>> {
+        if !self.changed {
+            return None;
+        }
+        self.changed = false;
+
+        let mut addresses = Vec::new();
+        addresses.push(EthernetAddress::from_multicast(Ipv4Addr::from(
+            ALL_HOSTS_GROUP,
+        )));
+        for x in self.groups.iter() {
+            addresses.push(EthernetAddress::from_multicast(x.address));
+        }
+        Some(addresses)
+    }
+}
+
+/// A pseudo-random delay of less than `max` ticks for answering a query about
+/// `group`.
+///
+/// There is no source of randomness, so the tick count and the group are
+/// mixed to spread the reports of different groups and hosts apart.
+fn random_delay(group: Ipv4Addr, max: u32) -> u32 {
+    let mut x = now() ^ u32::from_be_bytes(group.as_bytes()) ^ 0x9E37_79B9;
+    x ^= x << 13;
+    x ^= x >> 17;
+    x ^= x << 5;
+    x % max.max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An IGMPv3 general query, as routers send by default, 12 bytes long.
+    const V3_QUERY: [u8; 12] = [
+        0x11, 0x64, 0xec, 0x1e, 0x00, 0x00, 0x00, 0x00, 0x02, 0x7d, 0x00, 0x00,
+    ];
+
+    #[test]
+    fn test_from_buffer_v3_query() {
+        let packet = IgmpPacket::from_buffer(&V3_QUERY).unwrap();
+        assert_eq!(packet.r#type, Type::MembershipQuery);
+        assert_eq!(packet.max_response_time, 100);
+        assert!(packet.group.is_unspecified());
+
+        // The checksum covers the bytes past the first 8.
+        let mut buf = V3_QUERY;
+        buf[9] ^= 0x01;
+        assert!(IgmpPacket::from_buffer(&buf).is_err());
+    }
+
+    #[test]
+    fn test_from_buffer_v2_query() {
+        let buf = [0x11, 0x64, 0xee, 0x9b, 0x00, 0x00, 0x00, 0x00];
+        let packet = IgmpPacket::from_buffer(&buf).unwrap();
+        assert_eq!(packet.r#type, Type::MembershipQuery);
+    }
+
+    #[test]
+    fn test_from_buffer_unknown_type() {
+        // An IGMPv3 membership report is dropped.
+        let buf = [0x22, 0x00, 0xdd, 0xff, 0x00, 0x00, 0x00, 0x00];
+        assert!(IgmpPacket::from_buffer(&buf).is_err());
+    }
+}
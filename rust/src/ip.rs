@@ -1,3 +1,4 @@
+use crate::error::NetError;
 use crate::packet_buffer::{FromBuffer, ToBuffer};
 
 /// An IPv4 address.
@@ -18,6 +19,21 @@ impl Ipv4Addr {
     pub fn as_bytes(&self) -> [u8; 4] {
         self.0
     }
+
+    /// Is this the limited broadcast address, 255.255.255.255?
+    pub fn is_broadcast(&self) -> bool {
+        self.0 == [255, 255, 255, 255]
+    }
+
+    /// Is this a multicast group address, in 224.0.0.0/4?
+    pub fn is_multicast(&self) -> bool {
+        self.0[0] & 0xF0 == 0xE0
+    }
+
+    /// Is this the unspecified address, 0.0.0.0?
+    pub fn is_unspecified(&self) -> bool {
+        self.0 == [0, 0, 0, 0]
+    }
 }
 
 impl From<u32> for Ipv4Addr {
@@ -26,37 +42,90 @@ impl From<u32> for Ipv4Addr {
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+/// The protocol carried by an IPv4 packet.
+///
+/// Protocols the stack does not handle itself keep their protocol number, so
+/// they can still be delivered to raw sockets.
+#[derive(Debug, Copy, Clone, Eq, Ord, PartialEq, PartialOrd)]
 pub enum Protocol {
-    ICMP = 0x01,
-    TCP = 0x06,
-    UDP = 0x11,
-    UNKNOWN = 0xFF,
+    ICMP,
+    IGMP,
+    TCP,
+    UDP,
+    Other(u8),
 }
 
 impl Protocol {
     pub fn from_slice(buf: &[u8]) -> Protocol {
-        match buf[0] {
-            0x01u8 => Protocol::ICMP,
-            0x06u8 => Protocol::TCP,
-            0x11u8 => Protocol::UDP,
-            _ => Protocol::UNKNOWN,
-        }
+        Protocol::from(buf[0])
     }
 
-    fn as_bytes(&self) -> u8 {
+    pub fn as_bytes(&self) -> u8 {
         match self {
             Protocol::ICMP => 0x01u8,
+            Protocol::IGMP => 0x02u8,
             Protocol::TCP => 0x06u8,
             Protocol::UDP => 0x11u8,
-            Protocol::UNKNOWN => 0xFFu8,
+            Protocol::Other(x) => *x,
         }
     }
 }
 
+impl From<u8> for Protocol {
+    fn from(value: u8) -> Protocol {
+        match value {
+            0x01u8 => Protocol::ICMP,
+            0x02u8 => Protocol::IGMP,
+            0x06u8 => Protocol::TCP,
+            0x11u8 => Protocol::UDP,
+            x => Protocol::Other(x),
+        }
+    }
+}
+
+/// A piece of an IPv4 payload sent in a packet of its own.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Fragment {
+    /// The offset of the first byte of the fragment into the payload.
+    pub start: usize,
+    /// The offset just past the last byte of the fragment.
+    pub end: usize,
+    /// Do more fragments follow this one?
+    pub more: bool,
+}
+
+impl Fragment {
+    /// The fragment offset of the packet holding the fragment, in units of 8
+    /// bytes.
+    pub fn offset(&self) -> u16 {
+        (self.start / 8) as u16
+    }
+}
+
+/// Split a payload of `len` bytes into the fragments of packets that fit in
+/// `mtu` bytes, headers without options included.
+///
+/// Every fragment but the last holds a multiple of 8 bytes, the unit of the
+/// fragment offset. A payload that fits is a single fragment, as is an empty
+/// one.
+pub fn fragments(len: usize, mtu: usize) -> impl Iterator<Item = Fragment> {
+    let size = (mtu - 20) & !7;
+    let count = ((len + size - 1) / size).max(1);
+    (0..count).map(move |i| {
+        let start = i * size;
+        let end = len.min(start + size);
+        Fragment {
+            start: start,
+            end: end,
+            more: end < len,
+        }
+    })
+}
+
 /// An IPv4 packet.
 ///
-/// Represents an IPV4 packet header without options.
+/// Represents an IPV4 packet header. Options of received packets are skipped,
+/// packets are sent without options other than the router alert.
 ///
 /// RFC791 Section 3.1
 /// https://tools.ietf.org/html/rfc791
@@ -76,13 +145,17 @@ pub struct Ipv4Packet {
     header_checksum: u16,
     source_address: Ipv4Addr,
     destination_address: Ipv4Addr,
+    /// Send the router alert option, never set on received packets.
+    router_alert: bool,
 }
 
 impl Ipv4Packet {
     /// Creates a new Ipv4Header with the specified values.
     ///
     /// Headers are created with their checksum set to 0. Checksums are
-    /// calculated on write.
+    /// calculated on write. The DSCP is the upper 6 bits of the type of
+    /// service byte and the ECN the lower 2. The fragment offset is in units
+    /// of 8 bytes.
     pub fn new(
         dscp: u8,
         ecn: u8,
@@ -111,15 +184,20 @@ impl Ipv4Packet {
             header_checksum: 0,
             source_address: source_address,
             destination_address: destination_address,
+            router_alert: false,
         }
     }
 
     /// Creates a new Ipv4Header from a slice of bytes.
-    pub fn from_slice(buf: &[u8]) -> Result<Ipv4Packet, ()> {
+    pub fn from_slice(buf: &[u8]) -> Result<Ipv4Packet, NetError> {
+        if buf.len() < 20 {
+            return Err(NetError::Malformed);
+        }
+
         let packet = Ipv4Packet {
             version: buf[0] >> 4,
             header_length: buf[0] & 0xf,
-            dscp: buf[1] & 0xfc,
+            dscp: buf[1] >> 2,
             ecn: buf[1] & 0x3,
             total_length: u16::from_be_bytes([buf[2], buf[3]]),
             identification: u16::from_be_bytes([buf[4], buf[5]]),
@@ -131,11 +209,13 @@ impl Ipv4Packet {
             header_checksum: u16::from_be_bytes([buf[10], buf[11]]),
             source_address: Ipv4Addr::new(buf[12], buf[13], buf[14], buf[15]),
             destination_address: Ipv4Addr::new(buf[16], buf[17], buf[18], buf[19]),
+            router_alert: false,
         };
 
-        // Reject any packets with unexpected header lengths.
-        if packet.header_length != 5 {
-            return Err(());
+        // Options are skipped, routers send IGMP queries with the router alert
+        // option.
+        if packet.header_length < 5 || buf.len() < packet.header_length as usize * 4 {
+            return Err(NetError::Malformed);
         }
         return Ok(packet);
     }
@@ -148,13 +228,55 @@ impl Ipv4Packet {
         self.source_address
     }
 
+    pub fn destination(&self) -> Ipv4Addr {
+        self.destination_address
+    }
+
+    /// Is this one fragment of a larger packet?
+    pub fn is_fragment(&self) -> bool {
+        self.mf || self.fragment_offset != 0
+    }
+
+    /// The size of the header in bytes, options included.
+    pub fn header_size(&self) -> usize {
+        self.header_length as usize * 4
+    }
+
+    pub fn time_to_live(&self) -> u8 {
+        self.time_to_live
+    }
+
+    /// The length of the packet, header included.
+    pub fn total_length(&self) -> u16 {
+        self.total_length
+    }
+
+    pub fn set_total_length(&mut self, total_length: u16) {
+        self.total_length = total_length;
+    }
+
+    pub fn set_source(&mut self, source_address: Ipv4Addr) {
+        self.source_address = source_address;
+    }
+
+    /// Send the packet with the router alert option, which asks routers to
+    /// look at its contents. The header grows by 4 bytes, the total length is
+    /// left to the caller.
+    ///
+    /// RFC2113 Section 2.1
+    pub fn set_router_alert(&mut self) {
+        self.router_alert = true;
+        self.header_length = 6;
+    }
+
     /// Write the header to `buf` with the appropriate checksum.
     ///
     /// The header is written to a stack allocated buffer, the checksum
     /// calculated, then the header is written to `buf`.
     pub fn write(&self, buf: &mut [u8]) {
         // Temporary write buffer to make calculating the checksum easier.
-        let mut bytes = [0u8; 20];
+        let mut bytes = [0u8; 24];
+        let size = self.header_size();
 
         // Version and length.
         bytes[0] = {
@@ -168,7 +290,7 @@ impl Ipv4Packet {
         // DSCP and ECN.
         bytes[1] = {
             let mut byte = 0u8;
-            byte |= self.dscp & 0b11111100;
+            byte |= (self.dscp & 0b00111111) << 2;
             byte |= self.ecn & 0b00000011;
             byte
         };
@@ -182,8 +304,8 @@ impl Ipv4Packet {
             &{
                 let mut half = 0u16;
                 half |= (self.df as u16) << 14;
-                half |= (self.mf as u16) << 14;
-                half |= self.fragment_offset & 0x0FFF;
+                half |= (self.mf as u16) << 13;
+                half |= self.fragment_offset & 0x1FFF;
                 half
             }
             .to_be_bytes(),
@@ -200,12 +322,17 @@ impl Ipv4Packet {
         bytes[12..16].copy_from_slice(&self.source_address.as_bytes());
         bytes[16..20].copy_from_slice(&self.destination_address.as_bytes());
 
+        // Router alert, with a value of 0 asking routers to examine the packet.
+        if self.router_alert {
+            bytes[20..24].copy_from_slice(&[0x94, 0x04, 0x00, 0x00]);
+        }
+
         // Now `bytes` contains the complete header, calculate the checksum.
-        let checksum = &Ipv4Packet::calculate_checksum(&bytes);
+        let checksum = &Ipv4Packet::calculate_checksum(&bytes[..size]);
         bytes[10..12].copy_from_slice(&checksum.to_be_bytes());
 
         // Write the temporary buffer.
-        buf.copy_from_slice(&bytes);
+        buf.copy_from_slice(&bytes[..size]);
     }
 
     /// Calculates the checksum for a slice of bytes.
@@ -214,7 +341,7 @@ impl Ipv4Packet {
     /// https://tools.ietf.org/html/rfc791
     fn calculate_checksum(buf: &[u8]) -> u16 {
         let mut sum = 0u32;
-        for i in (0..20).step_by(2) {
+        for i in (0..buf.len()).step_by(2) {
             let value = u16::from_be_bytes([buf[i], buf[i + 1]]);
             sum += value as u32;
         }
@@ -226,12 +353,12 @@ impl Ipv4Packet {
 }
 
 impl FromBuffer for Ipv4Packet {
-    fn from_buffer(buf: &[u8]) -> Result<Ipv4Packet, ()> {
+    fn from_buffer(buf: &[u8]) -> Result<Ipv4Packet, NetError> {
         Ipv4Packet::from_slice(&buf)
     }
 
     fn size(&self) -> usize {
-        20
+        self.header_size()
     }
 }
 
@@ -241,17 +368,18 @@ impl ToBuffer for Ipv4Packet {
     }
 
     fn size(&self) -> usize {
-        20
+        self.header_size()
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use alloc::vec::Vec;
 
     #[test]
     fn test_write() {
-        let header = Ipv4Header::new(
+        let header = Ipv4Packet::new(
             0,
             0,
             60,
@@ -260,7 +388,7 @@ mod tests {
             false,
             0,
             64,
-            6,
+            Protocol::TCP,
             Ipv4Addr::new(10, 0, 0, 1),
             Ipv4Addr::new(10, 0, 0, 2),
         );
@@ -279,10 +407,11 @@ mod tests {
 
     #[test]
     fn test_from_slice() {
-        let header = Ipv4Header::from_slice(&[
+        let header = Ipv4Packet::from_slice(&[
             0x45, 0x0, 0x0, 0x3c, 0x13, 0x19, 0x40, 0x0, 0x40, 0x6, 0x13, 0xa1, 0xa, 0x0, 0x0, 0x1,
             0xa, 0x0, 0x0, 0x2,
-        ]);
+        ])
+        .unwrap();
 
         assert_eq!(header.version, 4);
         assert_eq!(header.header_length, 5);
@@ -294,9 +423,130 @@ mod tests {
         assert_eq!(header.mf, false);
         assert_eq!(header.fragment_offset, 0);
         assert_eq!(header.time_to_live, 64);
-        assert_eq!(header.protocol, 6);
+        assert_eq!(header.protocol, Protocol::TCP);
         assert_eq!(header.header_checksum, 5025);
         assert_eq!(header.source_address, Ipv4Addr::new(10, 0, 0, 1));
         assert_eq!(header.destination_address, Ipv4Addr::new(10, 0, 0, 2));
     }
+
+    #[test]
+    fn test_type_of_service() {
+        // IP_TOS of 0xb9, DSCP EF (46) with ECT(1), split as send_ip splits it.
+        let tos = 0xb9u8;
+        let header = Ipv4Packet::new(
+            tos >> 2,
+            tos & 0x3,
+            60,
+            4889,
+            true,
+            false,
+            0,
+            64,
+            Protocol::UDP,
+            Ipv4Addr::new(10, 0, 0, 1),
+            Ipv4Addr::new(10, 0, 0, 2),
+        );
+
+        let mut buf = [0u8; 20];
+        header.write(&mut buf);
+        assert_eq!(buf[1], tos);
+        assert_eq!(Ipv4Packet::calculate_checksum(&buf), 0);
+
+        let header = Ipv4Packet::from_slice(&buf).unwrap();
+        assert_eq!(header.dscp, 46);
+        assert_eq!(header.ecn, 1);
+    }
+
+    #[test]
+    fn test_router_alert() {
+        let mut header = Ipv4Packet::new(
+            0,
+            0,
+            32,
+            0,
+            true,
+            false,
+            0,
+            1,
+            Protocol::IGMP,
+            Ipv4Addr::new(10, 0, 0, 2),
+            Ipv4Addr::new(224, 0, 0, 22),
+        );
+        header.set_router_alert();
+        assert_eq!(ToBuffer::size(&header), 24);
+
+        let mut buf = [0u8; 24];
+        header.to_buffer(&mut buf);
+        assert_eq!(buf[0], 0x46);
+        assert_eq!(buf[20..24], [0x94, 0x04, 0x00, 0x00]);
+        assert_eq!(Ipv4Packet::calculate_checksum(&buf), 0);
+
+        let parsed = Ipv4Packet::from_slice(&buf).unwrap();
+        assert_eq!(parsed.header_size(), 24);
+        assert_eq!(parsed.destination(), Ipv4Addr::new(224, 0, 0, 22));
+    }
+
+    #[test]
+    fn test_fragments() {
+        // A 5000 byte UDP datagram, its header included, over ethernet.
+        let len = 5000 + 8;
+        let fragments: Vec<Fragment> = fragments(len, 1500).collect();
+        assert_eq!(
+            fragments
+                .iter()
+                .map(|x| (x.end - x.start, x.offset(), x.more))
+                .collect::<Vec<_>>(),
+            [
+                (1480, 0, true),
+                (1480, 185, true),
+                (1480, 370, true),
+                (568, 555, false)
+            ]
+        );
+        assert_eq!(fragments.last().unwrap().end, len);
+
+        // The offset and more fragments flag survive the header.
+        for fragment in fragments.iter() {
+            let header = Ipv4Packet::new(
+                0,
+                0,
+                (fragment.end - fragment.start + 20) as u16,
+                4889,
+                false,
+                fragment.more,
+                fragment.offset(),
+                64,
+                Protocol::UDP,
+                Ipv4Addr::new(10, 0, 0, 1),
+                Ipv4Addr::new(10, 0, 0, 2),
+            );
+            let mut buf = [0u8; 20];
+            header.write(&mut buf);
+
+            let header = Ipv4Packet::from_slice(&buf).unwrap();
+            assert!(header.is_fragment());
+            assert_eq!(header.mf, fragment.more);
+            assert_eq!(header.fragment_offset, fragment.offset());
+        }
+    }
+
+    #[test]
+    fn test_fragments_fit() {
+        assert_eq!(
+            fragments(1480, 1500).collect::<Vec<_>>(),
+            [Fragment {
+                start: 0,
+                end: 1480,
+                more: false
+            }]
+        );
+        assert_eq!(
+            fragments(0, 1500).collect::<Vec<_>>(),
+            [Fragment {
+                start: 0,
+                end: 0,
+                more: false
+            }]
+        );
+    }
 }
@@ -1,4 +1,4 @@
-use core::ffi::{c_int, c_uchar, c_void};
+use core::ffi::{c_int, c_uchar, c_uint, c_void};
 
 // Bindings to the existing xv6 kernel library.
 extern "C" {
@@ -8,13 +8,40 @@ extern "C" {
     // console.c
     pub fn cprint(c: *const c_uchar);
 
+    // file.c
+    pub fn filereadat(f: *mut c_void, addr: *mut c_uchar, off: c_uint, n: c_int) -> c_int;
+    pub fn pollwakeup();
+
     // kalloc.c
     pub fn kalloc() -> *mut c_void;
     pub fn kfree(ptr: *const c_void);
 
+    // proc.c
+    pub fn killed() -> c_int;
+    pub fn sleepunlock(
+        chan: *const c_void,
+        unlock: unsafe extern "C" fn(*mut c_void),
+        arg: *mut c_void,
+    );
+    pub fn wakeup(chan: *const c_void);
+
     // syscall.c
-    pub fn argint(n: c_int, ip: *mut c_int);
-    pub fn argptr(n: c_int, pp: *const *mut c_void, size: c_int);
+    pub fn argint(n: c_int, ip: *mut c_int) -> c_int;
+    pub fn argptr(n: c_int, pp: *const *mut c_void, size: c_int) -> c_int;
+    pub fn fetchptr(addr: c_uint, pp: *const *mut c_void, size: c_int) -> c_int;
+
+    // sysfile.c
+    pub fn argepoll(n: c_int, pep: *mut c_int) -> c_int;
+    pub fn argfile(n: c_int, pf: *mut *mut c_void) -> c_int;
+    pub fn argsock(n: c_int, sock: *mut c_int) -> c_int;
+    pub fn epollfdalloc(ep: c_int) -> c_int;
+    pub fn sockcreate(path: *const c_uchar) -> *mut c_void;
+    pub fn sockfdalloc(sock: c_int) -> c_int;
+    pub fn sockiput(ip: *mut c_void);
+    pub fn socklookup(path: *const c_uchar) -> *mut c_void;
+
+    // trap.c
+    pub static ticks: c_uint;
 
     // spinlock.c
     pub fn pushcli();
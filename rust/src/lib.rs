@@ -12,15 +12,23 @@ mod kernel;
 mod spinlock;
 
 mod arp;
-mod cpu;
+mod demux;
 mod e1000;
+mod epoll;
+mod error;
 mod ethernet;
+mod gather;
+mod handle;
 mod icmp;
+mod igmp;
 mod ip;
 mod mm;
 mod net;
 mod packet_buffer;
 mod pci;
+mod ports;
+mod tcp;
+mod timer;
 mod udp;
 
 #[panic_handler]
@@ -1,20 +1,35 @@
 use alloc::boxed::Box;
-use alloc::collections::btree_map::BTreeMap;
+use alloc::collections::vec_deque::VecDeque;
 use alloc::vec;
 use alloc::vec::Vec;
+use core::ffi::c_void;
 use core::slice;
+use core::sync::atomic::{AtomicU16, Ordering};
 
 use crate::arp;
 use crate::arp::{ArpCache, ArpPacket};
-use crate::cpu::{rdtsc, CPU_FREQ_MHZ};
+use crate::demux::{DemuxTable, Endpoint};
 use crate::e1000::E1000;
+use crate::epoll::Epoll;
+use crate::error::{syscall_return, NetError};
 use crate::ethernet::{EthernetAddress, EthernetFrame, Ethertype};
-use crate::icmp::IcmpPacket;
-use crate::icmp::{IcmpEchoMessage, Type};
+use crate::gather::{Chunks, Gather};
+use crate::handle::HandleTable;
+use crate::icmp::{IcmpEchoMessage, IcmpPacket, Type, PORT_UNREACHABLE};
+use crate::igmp;
+use crate::igmp::{GroupTable, IgmpPacket, ALL_ROUTERS_GROUP};
+use crate::ip;
 use crate::ip::{Ipv4Addr, Ipv4Packet, Protocol};
-use crate::kernel::{argint, argptr, cprint};
-use crate::packet_buffer::{PacketBuffer, BUFFER_SIZE};
+use crate::kernel::{
+    argepoll, argfile, argint, argptr, argsock, cprint, epollfdalloc, fetchptr, filereadat, killed,
+    pollwakeup, sockcreate, sockfdalloc, sockiput, socklookup, wakeup,
+};
+use crate::mm::PAGE_SIZE;
+use crate::packet_buffer::{FromBuffer, PacketBuffer};
+use crate::ports::{Binding, PortManager, EPHEMERAL_PORT_FIRST, EPHEMERAL_PORT_LAST};
 use crate::spinlock::Spinlock;
+use crate::timer;
+use crate::timer::{expired, now, wake_at};
 use crate::udp::UdpPacket;
 
 /// The system network device.
@@ -28,8 +43,162 @@ static NETWORK_DEVICE: Spinlock<Option<Box<dyn NetworkDevice>>> = Spinlock::new(
 /// ARP Cache.
 static ARP_CACHE: Spinlock<ArpCache> = Spinlock::new(ArpCache::new());
 
-/// Active system sockets.
-static SOCKETS: Spinlock<BTreeMap<usize, Socket>> = Spinlock::new(BTreeMap::new());
+/// Active system sockets, addressed by generation-counted handles.
+///
+/// Sockets are boxed so their address is stable and can be used as the
+/// channel processes sleep on while waiting for data.
+static SOCKETS: Spinlock<HandleTable<Box<Socket>>> = Spinlock::new(HandleTable::new(MAX_SOCKETS));
+
+/// Local ports held by sockets.
+///
+/// Must only be locked while holding the `SOCKETS` lock, or with no other
+/// network locks held.
+static PORTS: Spinlock<PortManager> =
+    Spinlock::new(PortManager::new(EPHEMERAL_PORT_FIRST, EPHEMERAL_PORT_LAST));
+
+/// Demultiplexing table mapping inbound packets to sockets.
+///
+/// Must only be locked while holding the `SOCKETS` lock.
+static DEMUX: Spinlock<DemuxTable> = Spinlock::new(DemuxTable::new());
+
+/// Multicast groups joined by sockets.
+///
+/// May be locked while holding the `NETWORK_DEVICE` or `SOCKETS` lock, no
+/// other network lock may be taken while holding it.
+static GROUPS: Spinlock<GroupTable> = Spinlock::new(GroupTable::new());
+
+/// Epoll instances, each holding the sockets registered with it.
+///
+/// Instances are boxed so their address can be used as the channel processes
+/// sleep on while waiting for events.
+///
+/// May be locked while holding the `SOCKETS` lock, no other network lock may
+/// be taken while holding it.
+static EPOLLS: Spinlock<HandleTable<Box<Epoll>>> = Spinlock::new(HandleTable::new(MAX_EPOLLS));
+
+/// The identification of the next IPv4 packet sent, telling the fragments of
+/// one packet apart from those of others.
+static IDENTIFICATION: AtomicU16 = AtomicU16::new(0);
+
+/// The most sockets open across the system.
+const MAX_SOCKETS: usize = 64;
+
+/// The most sockets a process can create. Sockets inherited across fork(...)
+/// do not count against the child.
+const MAX_SOCKETS_PER_PROCESS: usize = 16;
+
+/// The maximum number of datagrams queued on a socket.
+const MAX_QUEUED_DATAGRAMS: usize = 16;
+
+/// The most epoll instances open across the system.
+const MAX_EPOLLS: usize = 16;
+
+/// The range of sizes accepted for SO_RCVBUF and SO_SNDBUF, in bytes.
+const MIN_SOCKET_BUFFER: u32 = 256;
+const MAX_SOCKET_BUFFER: u32 = 64 * 1024;
+
+/// The default SO_RCVBUF of a socket.
+const DEFAULT_RCVBUF: u32 = 2048;
+
+/// The default SO_SNDBUF of a socket, large enough for any UDP datagram.
+const DEFAULT_SNDBUF: u32 = MAX_SOCKET_BUFFER;
+
+/// The most connections a listening unix domain socket holds waiting to be
+/// accepted.
+const MAX_BACKLOG: usize = 8;
+
+/// The most multicast groups a socket joins.
+const MAX_MEMBERSHIPS: usize = 20;
+
+/// The most buffers a sendmsg(...) or recvmsg(...) takes.
+const MAX_IOVECS: usize = 16;
+
+/// The size of the path of a unix domain socket address, including the
+/// terminating NUL.
+const UNIX_PATH_MAX: usize = 108;
+
+/// The address of the local adaptor.
+///
+/// TODO:
+/// 	- Don't hardcode the local adaptor address to 10.0.0.2
+const LOCAL_ADDRESS: u32 = 0x0A000002;
+
+/// The subnet mask of the local network, 255.255.255.0.
+const SUBNET_MASK: u32 = 0xFFFFFF00;
+
+/// The largest IPv4 packet sent, header included.
+const MTU: usize = 1500;
+
+/// The largest IPv4 packet, header included, sent whole or in fragments.
+const MAX_PACKET_SIZE: usize = 65535;
+
+/// The largest UDP datagram payload, leaving room for the IPv4 and UDP
+/// headers.
+const MAX_UDP_PAYLOAD: usize = MAX_PACKET_SIZE - 20 - 8;
+
+/// The size of an ethernet frame header.
+const ETHERNET_HEADER_SIZE: usize = 14;
+
+/// The default time to live of outgoing packets.
+const DEFAULT_TTL: u8 = 64;
+
+// Send and receive flags, these must match the values in socket.h.
+const MSG_PEEK: u32 = 0x02;
+const MSG_CTRUNC: u32 = 0x08;
+const MSG_TRUNC: u32 = 0x20;
+const MSG_DONTWAIT: u32 = 0x40;
+const MSG_WAITALL: u32 = 0x100;
+
+// Address families, socket types and protocols, these must match the values
+// in socket.h.
+const AF_UNIX: i32 = 1;
+const AF_INET: i32 = 2;
+const AF_PACKET: i32 = 17;
+const SOCK_STREAM: i32 = 1;
+const SOCK_DGRAM: i32 = 2;
+const SOCK_RAW: i32 = 3;
+const SOCK_NONBLOCK: i32 = 0x800;
+const IPPROTO_UDP: i32 = 17;
+const IPPROTO_RAW: i32 = 255;
+const ETH_P_ALL: u16 = 0x0003;
+
+// Socket option levels and names, these must match the values in socket.h.
+const SOL_SOCKET: i32 = 1;
+const SO_REUSEADDR: i32 = 2;
+const SO_ERROR: i32 = 4;
+const SO_BROADCAST: i32 = 6;
+const SO_SNDBUF: i32 = 7;
+const SO_RCVBUF: i32 = 8;
+const SO_REUSEPORT: i32 = 15;
+const SO_RCVTIMEO: i32 = 20;
+const SO_SNDTIMEO: i32 = 21;
+const SO_TIMESTAMP: i32 = 29;
+const IPPROTO_IP: i32 = 0;
+const IP_TOS: i32 = 1;
+const IP_TTL: i32 = 2;
+const IP_HDRINCL: i32 = 3;
+const IP_PKTINFO: i32 = 8;
+const IP_MTU_DISCOVER: i32 = 10;
+const IP_RECVTTL: i32 = 12;
+const IP_ADD_MEMBERSHIP: i32 = 35;
+const IP_DROP_MEMBERSHIP: i32 = 36;
+
+/// Values of IP_MTU_DISCOVER.
+const IP_PMTUDISC_DONT: i32 = 0;
+const IP_PMTUDISC_WANT: i32 = 1;
+const IP_PMTUDISC_DO: i32 = 2;
+
+// Epoll control operations, these must match the values in epoll.h.
+const EPOLL_CTL_ADD: i32 = 1;
+const EPOLL_CTL_DEL: i32 = 2;
+const EPOLL_CTL_MOD: i32 = 3;
+
+// Poll events, these must match the values in poll.h.
+const POLLIN: i32 = 0x001;
+const POLLOUT: i32 = 0x004;
+const POLLERR: i32 = 0x008;
+const POLLHUP: i32 = 0x010;
+const POLLNVAL: i32 = 0x020;
 
 /// Represents a device that can send and receive packets.
 pub trait NetworkDevice: Send + Sync {
@@ -46,16 +215,363 @@ pub trait NetworkDevice: Send + Sync {
     fn clear_interrupts(&mut self);
 
     /// Serialize a new packet.
-    fn send(&mut self, buf: PacketBuffer);
+    fn send(&mut self, buf: PacketBuffer) -> Result<(), NetError>;
+
+    /// Receive multicast frames sent to `addresses`, and no others.
+    fn set_multicast_filter(&mut self, addresses: &[EthernetAddress]);
 
     /// Receive a new packet.
     fn recv(&mut self) -> Option<PacketBuffer>;
 }
 
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 enum SocketType {
     _TCP,
     UDP,
+    /// A raw IPv4 socket for a single protocol.
+    Raw(Protocol),
+    /// A packet socket for whole ethernet frames of a single Ethertype, or of
+    /// every Ethertype with ETH_P_ALL.
+    Packet(u16),
+    /// A unix domain stream socket.
+    UnixStream,
+    /// A unix domain datagram socket.
+    UnixDgram,
+}
+
+/// The address and port of a socket.
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Peer {
+    address: Ipv4Addr,
+    port: u16,
+}
+
+/// An IPv4 socket address, laid out as `struct sockaddr_in` in socket.h.
+///
+/// The port and address are in network byte order.
+#[repr(C)]
+struct SockaddrIn {
+    family: u16,
+    port: [u8; 2],
+    address: [u8; 4],
+    zero: [u8; 8],
+}
+
+const SOCKADDR_IN_SIZE: usize = core::mem::size_of::<SockaddrIn>();
+
+impl Peer {
+    /// Fetch the nth system call argument as a pointer to a `struct
+    /// sockaddr_in`, with its length in the following argument.
+    unsafe fn from_user(n: i32) -> Result<Peer, NetError> {
+        Peer::from_sockaddr(buffer_arg(n)?)
+    }
+
+    /// Parse a `struct sockaddr_in` copied from user space.
+    fn from_sockaddr(addr: &[u8]) -> Result<Peer, NetError> {
+        if addr.len() < SOCKADDR_IN_SIZE {
+            return Err(NetError::InvalidArgument);
+        }
+        if u16::from_ne_bytes([addr[0], addr[1]]) != AF_INET as u16 {
+            return Err(NetError::AddressFamilyNotSupported);
+        }
+
+        Ok(Peer {
+            address: Ipv4Addr::from_slice(&addr[4..8]),
+            port: u16::from_be_bytes([addr[2], addr[3]]),
+        })
+    }
+}
+
+/// The NUL terminated path of a unix domain socket.
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct UnixPath([u8; UNIX_PATH_MAX]);
+
+/// A unix domain socket address, laid out as `struct sockaddr_un` in
+/// socket.h.
+#[repr(C)]
+struct SockaddrUn {
+    family: u16,
+    path: [u8; UNIX_PATH_MAX],
+}
+
+const SOCKADDR_UN_SIZE: usize = core::mem::size_of::<SockaddrUn>();
+
+impl UnixPath {
+    /// Fetch the nth system call argument as a pointer to a `struct
+    /// sockaddr_un`, with its length in the following argument.
+    unsafe fn from_user(n: i32) -> Result<UnixPath, NetError> {
+        UnixPath::from_sockaddr(buffer_arg(n)?)
+    }
+
+    /// Parse a `struct sockaddr_un` copied from user space.
+    ///
+    /// The path must be non-empty and NUL terminated within the address, or
+    /// end at the end of the address.
+    fn from_sockaddr(addr: &[u8]) -> Result<UnixPath, NetError> {
+        if addr.len() <= 2 || addr.len() > SOCKADDR_UN_SIZE {
+            return Err(NetError::InvalidArgument);
+        }
+        if u16::from_ne_bytes([addr[0], addr[1]]) != AF_UNIX as u16 {
+            return Err(NetError::AddressFamilyNotSupported);
+        }
+
+        // Leave room for the NUL.
+        let name = &addr[2..];
+        let end = name.iter().position(|x| *x == 0).unwrap_or(name.len());
+        if end == 0 || end >= UNIX_PATH_MAX {
+            return Err(NetError::InvalidArgument);
+        }
+        let mut path = [0; UNIX_PATH_MAX];
+        path[..end].copy_from_slice(&name[..end]);
+        Ok(UnixPath(path))
+    }
+
+    /// The length of the path, excluding the NUL.
+    fn len(&self) -> usize {
+        self.0.iter().position(|x| *x == 0).unwrap_or(UNIX_PATH_MAX)
+    }
+
+    /// A pointer to the path for the C side of the kernel.
+    fn as_ptr(&self) -> *const u8 {
+        self.0.as_ptr()
+    }
+}
+
+/// The sender of a received datagram.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Sender {
+    Inet(Peer),
+    /// A unix domain socket, by socket identifier. The path is looked up when
+    /// reported, so senders that have since closed are reported unnamed.
+    Unix(u32),
+}
+
+/// A user buffer a socket address is copied out to.
+struct SockaddrOut {
+    addr: *mut u8,
+    len: *mut u32,
+}
+
+impl SockaddrOut {
+    /// Fetch the nth system call argument as a pointer to a `struct
+    /// sockaddr_in` or `struct sockaddr_un`, and the following argument as a
+    /// pointer to its length.
+    ///
+    /// Returns `None` if the address pointer is null.
+    unsafe fn from_user(n: i32) -> Result<Option<SockaddrOut>, NetError> {
+        let mut raw: i32 = 0;
+        argint(n, &mut raw);
+        if raw == 0 {
+            return Ok(None);
+        }
+
+        let mut len: *mut u32 = core::ptr::null_mut();
+        if argptr(n + 1, &mut len as *const *mut u32 as _, 4) < 0 {
+            return Err(NetError::Fault);
+        }
+
+        let mut addr: *mut u8 = core::ptr::null_mut();
+        let size = (*len).min(SOCKADDR_UN_SIZE as u32);
+        if argptr(n, &mut addr as *const *mut u8 as _, size as i32) < 0 {
+            return Err(NetError::Fault);
+        }
+        Ok(Some(SockaddrOut { addr, len }))
+    }
+
+    /// Copy out a socket address, truncated to the size of the user buffer,
+    /// and set the length to the full size of the address.
+    unsafe fn write(&self, peer: Peer) {
+        let sockaddr = SockaddrIn {
+            family: AF_INET as u16,
+            port: peer.port.to_be_bytes(),
+            address: peer.address.as_bytes(),
+            zero: [0; 8],
+        };
+        let size = (*self.len).min(SOCKADDR_IN_SIZE as u32) as usize;
+        core::ptr::copy_nonoverlapping(
+            &sockaddr as *const SockaddrIn as *const u8,
+            self.addr,
+            size,
+        );
+        *self.len = SOCKADDR_IN_SIZE as u32;
+    }
+
+    /// Copy out a unix domain socket address, or an unnamed address with no
+    /// path, as write(...) does.
+    unsafe fn write_unix(&self, path: Option<&UnixPath>) {
+        let sockaddr = SockaddrUn {
+            family: AF_UNIX as u16,
+            path: path.map_or([0; UNIX_PATH_MAX], |x| x.0),
+        };
+        // The length covers the path and its NUL, as on other systems.
+        let len = match path {
+            Some(x) => 2 + x.len() + 1,
+            None => 2,
+        };
+        let size = (*self.len as usize).min(len);
+        core::ptr::copy_nonoverlapping(
+            &sockaddr as *const SockaddrUn as *const u8,
+            self.addr,
+            size,
+        );
+        *self.len = len as u32;
+    }
+
+    /// Copy out the address of the sender of received data.
+    ///
+    /// Must not be called with the `SOCKETS` lock held.
+    unsafe fn write_sender(&self, sender: Sender) {
+        match sender {
+            Sender::Inet(x) => self.write(x),
+            Sender::Unix(x) => {
+                let path = SOCKETS.lock().get(x as usize).and_then(|x| x.path);
+                self.write_unix(path.as_ref());
+            }
+        }
+    }
+}
+
+/// A message header, laid out as `struct msghdr` in socket.h.
+#[repr(C)]
+struct Msghdr {
+    name: u32,
+    namelen: u32,
+    iov: u32,
+    iovlen: u32,
+    control: u32,
+    controllen: u32,
+    flags: u32,
+}
+
+/// A user buffer, laid out as `struct iovec` in socket.h.
+#[repr(C)]
+struct Iovec {
+    base: u32,
+    len: u32,
+}
+
+/// The header of an ancillary data message, laid out as `struct cmsghdr` in
+/// socket.h.
+#[repr(C)]
+struct Cmsghdr {
+    len: u32,
+    level: i32,
+    r#type: i32,
+}
+
+/// The addresses of a received datagram, laid out as `struct in_pktinfo` in
+/// socket.h.
+#[repr(C)]
+struct InPktinfo {
+    ifindex: i32,
+    spec_dst: [u8; 4],
+    addr: [u8; 4],
+}
+
+impl Msghdr {
+    /// Fetch the nth system call argument as a pointer to a `struct msghdr`.
+    unsafe fn from_user(n: i32) -> Result<&'static mut Msghdr, NetError> {
+        let mut msg: *mut Msghdr = core::ptr::null_mut();
+        if argptr(
+            n,
+            &mut msg as *const *mut Msghdr as _,
+            core::mem::size_of::<Msghdr>() as i32,
+        ) < 0
+        {
+            return Err(NetError::Fault);
+        }
+        Ok(&mut *msg)
+    }
+
+    /// The user buffers of the message, at most `MAX_IOVECS`.
+    unsafe fn buffers(&self) -> Result<Vec<&'static mut [u8]>, NetError> {
+        if self.iovlen as usize > MAX_IOVECS {
+            return Err(NetError::MessageTooLong);
+        }
+        let iovecs = user_buffer(
+            self.iov,
+            self.iovlen as usize * core::mem::size_of::<Iovec>(),
+        )?;
+        let iovecs = slice::from_raw_parts(iovecs.as_ptr() as *const Iovec, self.iovlen as usize);
+
+        let mut buffers = Vec::new();
+        for iovec in iovecs.iter() {
+            buffers.push(user_buffer(iovec.base, iovec.len as usize)?);
+        }
+        Ok(buffers)
+    }
+}
+
+/// Writes ancillary data messages to the control buffer of a `struct msghdr`.
+struct ControlWriter {
+    buf: &'static mut [u8],
+    /// The number of bytes written.
+    len: usize,
+    /// A message did not fit and was dropped.
+    truncated: bool,
+}
+
+impl ControlWriter {
+    /// Append a message, or mark the buffer truncated if it does not fit.
+    fn push(&mut self, level: i32, r#type: i32, data: &[u8]) {
+        let header = core::mem::size_of::<Cmsghdr>();
+        // Messages are padded to keep the next header aligned.
+        let space = header + ((data.len() + 3) & !3);
+        if self.len + space > self.buf.len() {
+            self.truncated = true;
+            return;
+        }
+
+        let cmsghdr = Cmsghdr {
+            len: (header + data.len()) as u32,
+            level: level,
+            r#type: r#type,
+        };
+        let buf = &mut self.buf[self.len..self.len + space];
+        buf[..header].copy_from_slice(unsafe {
+            slice::from_raw_parts(&cmsghdr as *const Cmsghdr as *const u8, header)
+        });
+        buf[header..header + data.len()].copy_from_slice(data);
+        self.len += space;
+    }
+}
+
+/// The IPv4 header fields of a received datagram, reported to recvmsg(...) by
+/// IP_PKTINFO and IP_RECVTTL.
+#[derive(Debug, Copy, Clone)]
+struct PacketInfo {
+    /// The address the datagram was sent to.
+    destination: Ipv4Addr,
+    time_to_live: u8,
+}
+
+/// A datagram waiting to be read from a socket.
+#[derive(Debug)]
+struct Datagram {
+    /// The sender of the datagram, `None` for frames on packet sockets and
+    /// data on unix domain stream sockets.
+    peer: Option<Sender>,
+    /// The tick count at which the datagram arrived.
+    timestamp: u32,
+    /// The IPv4 header of the datagram, `None` unless it arrived over IPv4.
+    info: Option<PacketInfo>,
+    /// The datagram payload.
+    data: Chunks,
+}
+
+/// What recv(...) received.
+struct Received {
+    /// The number of bytes received, or with MSG_TRUNC the full length of the
+    /// datagram received.
+    len: u32,
+    /// The sender of the data.
+    peer: Option<Sender>,
+    /// The tick count at which the data arrived.
+    timestamp: u32,
+    /// The IPv4 header of the datagram received.
+    info: Option<PacketInfo>,
+    /// Part of the datagram did not fit and was discarded.
+    truncated: bool,
 }
 
 /// Represents one end of a socket connection.
@@ -67,7 +583,518 @@ struct Socket {
     dest_port: Option<u16>,
     dest_protocol_address: Option<Ipv4Addr>,
     dest_hardware_address: Option<EthernetAddress>,
-    buffer: Vec<u8>,
+    /// Received datagrams, oldest first.
+    queue: VecDeque<Datagram>,
+    /// The number of payload bytes held in `queue`.
+    queued: usize,
+    /// The number of datagrams dropped because `queue` was full.
+    drops: u32,
+    /// Multicast groups joined with IP_ADD_MEMBERSHIP.
+    groups: Vec<Ipv4Addr>,
+    /// The epoll instances the socket is registered with.
+    watchers: Vec<u32>,
+    shutdown: bool,
+    /// The pids of the processes that hold the socket, the creator and any
+    /// children forked since.
+    owners: Vec<u32>,
+    /// The pid of the process the socket counts against for
+    /// `MAX_SOCKETS_PER_PROCESS`, the one that created it.
+    creator: u32,
+    /// Set with SO_REUSEADDR.
+    reuse_address: bool,
+    /// Set with SO_REUSEPORT.
+    reuse_port: bool,
+    /// The most payload bytes held in `queue`, set with SO_RCVBUF.
+    rcvbuf: u32,
+    /// The largest datagram that can be sent, set with SO_SNDBUF.
+    sndbuf: u32,
+    /// How long a recv(...) waits for data, in ticks, set with SO_RCVTIMEO.
+    /// Zero waits forever.
+    rcvtimeo: u32,
+    /// How long a connect(...) or sendto(...) waits to resolve its
+    /// destination, in ticks, set with SO_SNDTIMEO. Zero waits until ARP
+    /// gives up.
+    sndtimeo: u32,
+    /// Set with SO_BROADCAST to allow sending to the broadcast address.
+    broadcast: bool,
+    /// A pending error, reported and cleared through SO_ERROR or by the next
+    /// send or receive.
+    error: Option<NetError>,
+    /// The time to live of outgoing packets, set with IP_TTL.
+    ttl: u8,
+    /// The type of service of outgoing packets, set with IP_TOS.
+    tos: u8,
+    /// Set with SO_TIMESTAMP to report the arrival time of data to
+    /// recvmsg(...).
+    timestamps: bool,
+    /// Set with IP_PKTINFO to report the destination address of datagrams to
+    /// recvmsg(...).
+    packet_info: bool,
+    /// Set with IP_RECVTTL to report the time to live of datagrams to
+    /// recvmsg(...).
+    receive_ttl: bool,
+    /// Set with SOCK_NONBLOCK, operations fail rather than block.
+    nonblocking: bool,
+    /// A connect(...) is waiting on the hardware address of the remote.
+    resolving: bool,
+    /// Set with IP_HDRINCL on raw sockets, data sent includes the IPv4
+    /// header.
+    header_included: bool,
+    /// Whether packets larger than the MTU are fragmented, set with
+    /// IP_MTU_DISCOVER.
+    mtu_discover: i32,
+    /// The path a unix domain socket is bound to. Sockets accepted from a
+    /// listener report the path of the listener.
+    path: Option<UnixPath>,
+    /// The socket inode held by a bound unix domain socket.
+    inode: Option<usize>,
+    /// The unix domain socket data is sent to.
+    remote: Option<u32>,
+    /// The remote of a unix domain stream socket has closed or shut down,
+    /// reads return end-of-file once the queue drains.
+    hangup: bool,
+    /// Set by listen(...) on a unix domain stream socket.
+    listening: bool,
+    /// Connections waiting to be accepted, oldest first.
+    backlog: VecDeque<u32>,
+    /// The most connections held in `backlog`, set by listen(...).
+    max_backlog: usize,
+}
+
+/// How the packets of a socket are sent, captured so they can be built without
+/// holding the `SOCKETS` lock.
+struct Outgoing {
+    r#type: SocketType,
+    source: Peer,
+    ttl: u8,
+    tos: u8,
+    header_included: bool,
+    mtu_discover: i32,
+}
+
+/// A socket option available through setsockopt(...) and getsockopt(...).
+///
+/// Every option takes an `int` value. New options are added by extending
+/// `SOCKET_OPTIONS`.
+struct SocketOption {
+    level: i32,
+    name: i32,
+    /// Apply a new value to the socket, `None` for read-only options.
+    set: Option<fn(&mut Socket, i32) -> Result<(), NetError>>,
+    /// Read the current value from the socket.
+    get: fn(&mut Socket) -> i32,
+}
+
+/// The registry of supported socket options.
+static SOCKET_OPTIONS: [SocketOption; 15] = [
+    SocketOption {
+        level: SOL_SOCKET,
+        name: SO_REUSEADDR,
+        set: Some(|socket, value| {
+            socket.reuse_address = value != 0;
+            Ok(())
+        }),
+        get: |socket| socket.reuse_address as i32,
+    },
+    SocketOption {
+        level: SOL_SOCKET,
+        name: SO_REUSEPORT,
+        set: Some(|socket, value| {
+            socket.reuse_port = value != 0;
+            Ok(())
+        }),
+        get: |socket| socket.reuse_port as i32,
+    },
+    SocketOption {
+        level: SOL_SOCKET,
+        name: SO_RCVBUF,
+        set: Some(|socket, value| {
+            socket.rcvbuf = (value.max(0) as u32).clamp(MIN_SOCKET_BUFFER, MAX_SOCKET_BUFFER);
+            Ok(())
+        }),
+        get: |socket| socket.rcvbuf as i32,
+    },
+    SocketOption {
+        level: SOL_SOCKET,
+        name: SO_SNDBUF,
+        set: Some(|socket, value| {
+            socket.sndbuf = (value.max(0) as u32).clamp(MIN_SOCKET_BUFFER, MAX_SOCKET_BUFFER);
+            Ok(())
+        }),
+        get: |socket| socket.sndbuf as i32,
+    },
+    SocketOption {
+        level: SOL_SOCKET,
+        name: SO_RCVTIMEO,
+        set: Some(|socket, value| {
+            socket.rcvtimeo = value.try_into().map_err(|_| NetError::InvalidArgument)?;
+            Ok(())
+        }),
+        get: |socket| socket.rcvtimeo as i32,
+    },
+    SocketOption {
+        level: SOL_SOCKET,
+        name: SO_SNDTIMEO,
+        set: Some(|socket, value| {
+            socket.sndtimeo = value.try_into().map_err(|_| NetError::InvalidArgument)?;
+            Ok(())
+        }),
+        get: |socket| socket.sndtimeo as i32,
+    },
+    SocketOption {
+        level: SOL_SOCKET,
+        name: SO_BROADCAST,
+        set: Some(|socket, value| {
+            socket.broadcast = value != 0;
+            Ok(())
+        }),
+        get: |socket| socket.broadcast as i32,
+    },
+    SocketOption {
+        level: SOL_SOCKET,
+        name: SO_ERROR,
+        set: None,
+        get: |socket| socket.error.take().map_or(0, |x| x.code()),
+    },
+    SocketOption {
+        level: SOL_SOCKET,
+        name: SO_TIMESTAMP,
+        set: Some(|socket, value| {
+            socket.timestamps = value != 0;
+            Ok(())
+        }),
+        get: |socket| socket.timestamps as i32,
+    },
+    SocketOption {
+        level: IPPROTO_IP,
+        name: IP_TTL,
+        set: Some(|socket, value| {
+            socket.ttl = match value {
+                1..=255 => value as u8,
+                _ => return Err(NetError::InvalidArgument),
+            };
+            Ok(())
+        }),
+        get: |socket| socket.ttl as i32,
+    },
+    SocketOption {
+        level: IPPROTO_IP,
+        name: IP_TOS,
+        set: Some(|socket, value| {
+            socket.tos = value.try_into().map_err(|_| NetError::InvalidArgument)?;
+            Ok(())
+        }),
+        get: |socket| socket.tos as i32,
+    },
+    SocketOption {
+        level: IPPROTO_IP,
+        name: IP_HDRINCL,
+        set: Some(|socket, value| {
+            if !socket.is_raw() {
+                return Err(NetError::UnknownOption);
+            }
+            socket.header_included = value != 0;
+            Ok(())
+        }),
+        get: |socket| socket.header_included as i32,
+    },
+    SocketOption {
+        level: IPPROTO_IP,
+        name: IP_MTU_DISCOVER,
+        set: Some(|socket, value| {
+            socket.mtu_discover = match value {
+                IP_PMTUDISC_DONT | IP_PMTUDISC_WANT | IP_PMTUDISC_DO => value,
+                _ => return Err(NetError::InvalidArgument),
+            };
+            Ok(())
+        }),
+        get: |socket| socket.mtu_discover,
+    },
+    SocketOption {
+        level: IPPROTO_IP,
+        name: IP_PKTINFO,
+        set: Some(|socket, value| {
+            socket.packet_info = value != 0;
+            Ok(())
+        }),
+        get: |socket| socket.packet_info as i32,
+    },
+    SocketOption {
+        level: IPPROTO_IP,
+        name: IP_RECVTTL,
+        set: Some(|socket, value| {
+            socket.receive_ttl = value != 0;
+            Ok(())
+        }),
+        get: |socket| socket.receive_ttl as i32,
+    },
+];
+
+impl SocketOption {
+    /// Look up an option in the registry.
+    fn find(level: i32, name: i32) -> Option<&'static SocketOption> {
+        SOCKET_OPTIONS
+            .iter()
+            .find(|x| x.level == level && x.name == name)
+    }
+}
+
+impl Socket {
+    /// Create a socket held by the processes `owners`, created by `creator`.
+    fn new(r#type: SocketType, creator: u32, owners: Vec<u32>, nonblocking: bool) -> Socket {
+        Socket {
+            r#type: r#type,
+            source_port: None,
+            source_address: None,
+            dest_port: None,
+            dest_protocol_address: None,
+            dest_hardware_address: None,
+            queue: VecDeque::new(),
+            queued: 0,
+            drops: 0,
+            groups: Vec::new(),
+            watchers: Vec::new(),
+            shutdown: false,
+            owners: owners,
+            creator: creator,
+            reuse_address: false,
+            reuse_port: false,
+            rcvbuf: DEFAULT_RCVBUF,
+            sndbuf: DEFAULT_SNDBUF,
+            rcvtimeo: 0,
+            sndtimeo: 0,
+            broadcast: false,
+            error: None,
+            ttl: DEFAULT_TTL,
+            tos: 0,
+            timestamps: false,
+            packet_info: false,
+            receive_ttl: false,
+            nonblocking: nonblocking,
+            resolving: false,
+            // IPPROTO_RAW sockets only send, and always supply the header.
+            header_included: r#type == SocketType::Raw(Protocol::Other(IPPROTO_RAW as u8)),
+            mtu_discover: IP_PMTUDISC_WANT,
+            path: None,
+            inode: None,
+            remote: None,
+            hangup: false,
+            listening: false,
+            backlog: VecDeque::new(),
+            max_backlog: 0,
+        }
+    }
+
+    /// The transport protocol of the socket.
+    fn protocol(&self) -> Protocol {
+        match self.r#type {
+            SocketType::_TCP => Protocol::TCP,
+            SocketType::UDP => Protocol::UDP,
+            SocketType::Raw(x) => x,
+            // Packet and unix domain sockets never hold ports or reach the
+            // demultiplexing table.
+            SocketType::Packet(_) | SocketType::UnixStream | SocketType::UnixDgram => {
+                Protocol::Other(0)
+            }
+        }
+    }
+
+    /// Is this a raw socket?
+    fn is_raw(&self) -> bool {
+        matches!(self.r#type, SocketType::Raw(_))
+    }
+
+    /// Is this a packet socket?
+    fn is_packet(&self) -> bool {
+        matches!(self.r#type, SocketType::Packet(_))
+    }
+
+    /// Is this a unix domain socket?
+    fn is_unix(&self) -> bool {
+        matches!(self.r#type, SocketType::UnixStream | SocketType::UnixDgram)
+    }
+
+    /// Describe the socket to the port manager.
+    fn binding(&self, socket_id: usize, address: Ipv4Addr) -> Binding {
+        Binding {
+            socket: socket_id,
+            address: address,
+            reuse_address: self.reuse_address,
+            reuse_port: self.reuse_port,
+        }
+    }
+
+    /// Describe the socket to the demultiplexing table.
+    ///
+    /// Must only be called once the socket is bound.
+    fn endpoint(&self, socket_id: usize) -> Endpoint {
+        Endpoint {
+            socket: socket_id,
+            local_address: self.source_address.unwrap(),
+            local_port: self.source_port.unwrap(),
+            remote_address: self.dest_protocol_address.unwrap_or(Ipv4Addr::from(0u32)),
+            remote_port: self.dest_port.unwrap_or(0),
+        }
+    }
+
+    /// The address packets from the socket are sent from.
+    fn local_address(&self) -> Ipv4Addr {
+        match self.source_address {
+            Some(x) if !x.is_unspecified() => x,
+            _ => Ipv4Addr::from(LOCAL_ADDRESS),
+        }
+    }
+
+    /// Capture how packets from the socket are sent.
+    fn outgoing(&self) -> Outgoing {
+        Outgoing {
+            r#type: self.r#type,
+            source: Peer {
+                address: self.local_address(),
+                port: self.source_port.unwrap_or(0),
+            },
+            ttl: self.ttl,
+            tos: self.tos,
+            header_included: self.header_included,
+            mtu_discover: self.mtu_discover,
+        }
+    }
+
+    /// Bind the socket to a local address and port, registering it to receive
+    /// packets sent there.
+    ///
+    /// Raw sockets have no ports, binding one only restricts it to packets
+    /// sent to `address`.
+    fn bind_to(&mut self, socket_id: usize, address: Ipv4Addr, port: u16) -> Result<(), NetError> {
+        if self.is_raw() {
+            self.source_address = Some(address);
+            return Ok(());
+        }
+
+        let binding = self.binding(socket_id, address);
+        let port = match port {
+            0 => PORTS.lock().allocate(self.protocol(), binding)?,
+            _ => {
+                PORTS.lock().bind(self.protocol(), port, binding)?;
+                port
+            }
+        };
+        self.source_port = Some(port);
+        self.source_address = Some(address);
+        DEMUX
+            .lock()
+            .insert(self.protocol(), self.endpoint(socket_id));
+        Ok(())
+    }
+
+    /// Bind the socket to any local address and a free ephemeral port, unless
+    /// it is already bound or is a raw socket.
+    fn bind_ephemeral(&mut self, socket_id: usize) -> Result<(), NetError> {
+        if self.source_port.is_some() || self.is_raw() {
+            return Ok(());
+        }
+        self.bind_to(socket_id, Ipv4Addr::from(0u32), 0)
+    }
+
+    /// Forget the remote of a socket, delivering packets from any host to it
+    /// again.
+    fn disconnect(&mut self, socket_id: usize) {
+        self.dest_port = None;
+        self.dest_protocol_address = None;
+        self.dest_hardware_address = None;
+        self.resolving = false;
+        if self.source_port.is_some() {
+            DEMUX
+                .lock()
+                .insert(self.protocol(), self.endpoint(socket_id));
+        }
+    }
+
+    /// Release the resources held by a socket that is being removed.
+    fn release(&self, socket_id: usize) {
+        if let Some(port) = self.source_port {
+            PORTS.lock().release(self.protocol(), port, socket_id);
+            DEMUX.lock().remove(self.protocol(), port, socket_id);
+        }
+        if self.is_raw() {
+            DEMUX.lock().remove_raw(socket_id);
+        }
+        if self.is_packet() {
+            DEMUX.lock().remove_frame(socket_id);
+        }
+
+        // Groups are left once their last member is gone.
+        let mut groups = GROUPS.lock();
+        for x in self.groups.iter() {
+            groups.leave(*x);
+        }
+        drop(groups);
+
+        // Registrations go with the socket.
+        let mut epolls = EPOLLS.lock();
+        for x in self.watchers.iter() {
+            if let Some(epoll) = epolls.get_mut(*x as usize) {
+                let _ = epoll.remove(socket_id as u32);
+            }
+        }
+        drop(epolls);
+
+        // Wake any readers so they notice the socket is gone.
+        unsafe { wakeup(self.channel()) };
+    }
+
+    /// The channel processes sleep on while waiting on this socket.
+    fn channel(&self) -> *const c_void {
+        self as *const Socket as *const c_void
+    }
+
+    /// Tell the epoll instances the socket is registered with that it may have
+    /// become ready, waking any process waiting on them.
+    fn notify(&self, socket_id: usize) {
+        if self.watchers.is_empty() {
+            return;
+        }
+        let mut epolls = EPOLLS.lock();
+        for x in self.watchers.iter() {
+            if let Some(epoll) = epolls.get_mut(*x as usize) {
+                epoll.notify(socket_id as u32);
+                unsafe { wakeup(epoll.channel()) };
+            }
+        }
+    }
+
+    /// Queue a received datagram.
+    ///
+    /// The queue is bounded both in the number of datagrams and the number of
+    /// payload bytes it holds. Datagrams that would exceed either limit are
+    /// dropped and counted.
+    fn enqueue(&mut self, datagram: Datagram) -> Result<(), NetError> {
+        if self.queue.len() >= MAX_QUEUED_DATAGRAMS
+            || self.queued + datagram.data.len() > self.rcvbuf as usize
+        {
+            self.drops += 1;
+            return Err(NetError::NoBuffers);
+        }
+        self.queued += datagram.data.len();
+        self.queue.push_back(datagram);
+        Ok(())
+    }
+
+    /// Remove the oldest datagram from the queue.
+    fn dequeue(&mut self) -> Option<Datagram> {
+        let datagram = self.queue.pop_front()?;
+        self.queued -= datagram.data.len();
+        Some(datagram)
+    }
+
+    /// The number of payload bytes that can be queued without exceeding the
+    /// limits of `enqueue`.
+    fn room(&self) -> usize {
+        if self.queue.len() >= MAX_QUEUED_DATAGRAMS {
+            return 0;
+        }
+        (self.rcvbuf as usize).saturating_sub(self.queued)
+    }
 }
 
 /// Initialize the network stack.
@@ -87,12 +1114,15 @@ unsafe extern "C" fn rustnetinit() {
     // Assign a hardcoded, static IP to the device for now.
     let mut network_device = NETWORK_DEVICE.lock();
     let mut device = Box::new(e1000_device);
-    device.set_protocol_address(Ipv4Addr::from(0x0A000002 as u32));
+    device.set_protocol_address(Ipv4Addr::from(LOCAL_ADDRESS));
+    if let Some(x) = GROUPS.lock().filter() {
+        device.set_multicast_filter(&x);
+    }
     *network_device = Some(device);
 
     // Setup other buffers and caches.
     let mut sockets = SOCKETS.lock();
-    *sockets = BTreeMap::new();
+    *sockets = HandleTable::new(MAX_SOCKETS);
 
     let mut arp_cache = ARP_CACHE.lock();
     *arp_cache = ArpCache::new();
@@ -104,341 +1134,2428 @@ unsafe extern "C" fn netintr() {
     handle_interrupt();
 }
 
-/// The socket system call.
+/// Entrypoint for clock ticks.
+///
+/// Called from the timer interrupt in trap.c to fire expired timers and retry
+/// outstanding ARP requests.
+#[no_mangle]
+unsafe extern "C" fn nettick() {
+    timer::tick();
+    retry_arp();
+    update_groups();
+}
+
+/// Allocate a new socket for the socket system call.
+///
+/// Called by `sys_socket` in sysfile.c, which wraps the returned socket
+/// identifier in a `FD_SOCKET` file. The socket is owned by the process `pid`.
+/// Returns -EPROTONOSUPPORT for unsupported combinations of domain, type and
+/// protocol, -EMFILE if the process holds `MAX_SOCKETS_PER_PROCESS` sockets it
+/// created and -ENFILE if the system holds `MAX_SOCKETS` sockets.
+#[no_mangle]
+unsafe extern "C" fn sockalloc(domain: i32, r#type: i32, protocol: i32, pid: i32) -> i32 {
+    let nonblocking = r#type & SOCK_NONBLOCK != 0;
+    let domain = match (domain, r#type & !SOCK_NONBLOCK, protocol) {
+        (AF_INET, SOCK_DGRAM, 0 | IPPROTO_UDP) => SocketType::UDP,
+        (AF_INET, SOCK_RAW, 1..=IPPROTO_RAW) => SocketType::Raw(Protocol::from(protocol as u8)),
+        // The Ethertype is given in network byte order.
+        (AF_PACKET, SOCK_RAW, 0..=0xFFFF) => SocketType::Packet(u16::from_be(protocol as u16)),
+        (AF_UNIX, SOCK_STREAM, 0) => SocketType::UnixStream,
+        (AF_UNIX, SOCK_DGRAM, 0) => SocketType::UnixDgram,
+        _ => return NetError::ProtocolNotSupported.to_syscall(),
+    };
+
+    match create_socket(domain, pid as u32, nonblocking) {
+        Ok(x) => x as i32,
+        Err(x) => x.to_syscall(),
+    }
+}
+
+/// Allocate a pair of connected sockets for the socketpair system call.
+///
+/// Called by `sys_socketpair` in sysfile.c, which wraps each socket in a
+/// `FD_SOCKET` file. The sockets are owned by the process `pid` and their
+/// identifiers are written to `pair`. Only unix domain sockets can be paired,
+/// other domains fail with -EOPNOTSUPP. Otherwise fails as sockalloc(...)
+/// does.
+#[no_mangle]
+unsafe extern "C" fn sockpair(
+    domain: i32,
+    r#type: i32,
+    protocol: i32,
+    pid: i32,
+    pair: *mut i32,
+) -> i32 {
+    let nonblocking = r#type & SOCK_NONBLOCK != 0;
+    let r#type = match (domain, r#type & !SOCK_NONBLOCK, protocol) {
+        (AF_UNIX, SOCK_STREAM, 0) => SocketType::UnixStream,
+        (AF_UNIX, SOCK_DGRAM, 0) => SocketType::UnixDgram,
+        (AF_UNIX, _, _) => return NetError::ProtocolNotSupported.to_syscall(),
+        _ => return NetError::OperationNotSupported.to_syscall(),
+    };
+
+    match create_pair(r#type, pid as u32, nonblocking) {
+        Ok((first, second)) => {
+            *pair = first as i32;
+            *pair.add(1) = second as i32;
+            0
+        }
+        Err(x) => x.to_syscall(),
+    }
+}
+
+/// Check whether the process `pid` owns a socket.
+///
+/// Called by `argsock` in sysfile.c, and by read(...), write(...) and poll(...)
+/// in file.c, to reject handles belonging to other processes.
+#[no_mangle]
+unsafe extern "C" fn sockowned(socket_id: i32, pid: i32) -> i32 {
+    let sockets = SOCKETS.lock();
+    match sockets.get(socket_id as usize) {
+        Some(x) => x.owners.contains(&(pid as u32)) as i32,
+        None => 0,
+    }
+}
+
+/// Share the sockets of a process with a newly forked child.
+///
+/// Called by `fork` in proc.c, the child inherits the parent's descriptors and
+/// so becomes an owner of each of the parent's sockets.
+#[no_mangle]
+unsafe extern "C" fn sockfork(parent: i32, child: i32) {
+    let mut sockets = SOCKETS.lock();
+    for socket in sockets.values_mut() {
+        if socket.owners.contains(&(parent as u32)) {
+            socket.owners.push(child as u32);
+        }
+    }
+}
+
+/// Release the sockets of an exiting process.
 ///
-/// Creates a new Socket entry and returns the socket identifier. Note,
-/// descriptors returned by this method are not currently compatable with the
-/// generalized read(...) and write(...) system calls.
+/// Called by `exit` in proc.c after the process has closed its files. Removes
+/// `pid` from the owners of every socket and tears down any socket left without
+/// an owner, freeing its port and queued datagrams.
+#[no_mangle]
+unsafe extern "C" fn sockexit(pid: i32) {
+    let mut sockets = SOCKETS.lock();
+    let mut orphans = Vec::new();
+    for (socket_id, socket) in sockets.iter_mut() {
+        socket.owners.retain(|x| *x != pid as u32);
+        if socket.owners.is_empty() {
+            orphans.push(socket_id);
+        }
+    }
+
+    // Socket inodes are released once the lock is dropped, as releasing them
+    // may sleep.
+    let mut inodes = Vec::new();
+    for socket_id in orphans {
+        if let Ok(Some(x)) = remove_socket(&mut sockets, socket_id) {
+            inodes.push(x);
+        }
+    }
+    drop(sockets);
+    for inode in inodes {
+        sockiput(inode as *mut c_void);
+    }
+}
+
+/// Release a socket once the last file referencing it is closed.
+#[no_mangle]
+unsafe extern "C" fn sockclose(socket_id: i32) {
+    let _ = close_socket(socket_id as u32);
+}
+
+/// Read from a socket through the generalized read(...) system call.
+#[no_mangle]
+unsafe extern "C" fn sockread(socket_id: i32, addr: *mut u8, n: i32) -> i32 {
+    if n < 0 {
+        return NetError::InvalidArgument.to_syscall();
+    }
+    let data = slice::from_raw_parts_mut(addr, n as usize);
+
+    syscall_return(recv(socket_id as u32, &mut [data], 0).map(|x| x.len))
+}
+
+/// Write to a socket through the generalized write(...) system call.
+#[no_mangle]
+unsafe extern "C" fn sockwrite(socket_id: i32, addr: *const u8, n: i32) -> i32 {
+    if n < 0 {
+        return NetError::InvalidArgument.to_syscall();
+    }
+    let data = slice::from_raw_parts(addr, n as usize);
+
+    syscall_return(send(socket_id as u32, Gather::new(&[data]), 0))
+}
+
+/// Report which poll events are ready on a socket.
 #[no_mangle]
-unsafe extern "C" fn sys_socket() -> i32 {
-    let mut domain: i32 = 0;
-    argint(0, &mut domain);
+unsafe extern "C" fn sockpoll(socket_id: i32) -> i32 {
+    socket_events(&SOCKETS.lock(), socket_id as usize)
+}
+
+/// The poll events ready on a socket.
+fn socket_events(sockets: &HandleTable<Box<Socket>>, socket_id: usize) -> i32 {
+    let socket = match sockets.get(socket_id) {
+        Some(x) => x,
+        None => return POLLNVAL,
+    };
 
-    let domain = match domain {
-        0 => SocketType::UDP,
-        _ => return -1,
+    if socket.shutdown {
+        return POLLIN | POLLHUP;
+    }
+    if socket.listening {
+        return if socket.backlog.is_empty() { 0 } else { POLLIN };
+    }
+
+    // Network sends never block, so the socket is writable unless a
+    // connect(...) is still in progress. Unix domain sockets are writable
+    // while their remote has room.
+    let writable = match (socket.is_unix(), socket.remote) {
+        (true, Some(x)) => sockets.get(x as usize).map_or(false, |x| x.room() > 0),
+        (true, None) => socket.r#type == SocketType::UnixDgram,
+        (false, _) => !socket.resolving,
     };
 
-    let socket_id = create_socket(domain);
-    socket_id as i32
+    let mut events = 0;
+    if writable {
+        events |= POLLOUT;
+    }
+    if !socket.queue.is_empty() {
+        events |= POLLIN;
+    }
+    if socket.hangup {
+        events |= POLLIN | POLLHUP;
+    }
+    if socket.error.is_some() {
+        events |= POLLERR;
+    }
+    events
+}
+
+/// Release an epoll instance once the last file referencing it is closed.
+#[no_mangle]
+unsafe extern "C" fn epollclose(epoll_id: i32) {
+    let mut sockets = SOCKETS.lock();
+    let epoll = match EPOLLS.lock().remove(epoll_id as usize) {
+        Some(x) => x,
+        None => return,
+    };
+    for x in epoll.sockets() {
+        if let Some(socket) = sockets.get_mut(x as usize) {
+            socket.watchers.retain(|y| *y != epoll_id as u32);
+        }
+    }
+}
+
+/// Report which poll events are ready on an epoll instance, POLLIN while a
+/// registered socket has events to report.
+#[no_mangle]
+unsafe extern "C" fn epollpoll(epoll_id: i32) -> i32 {
+    let sockets = SOCKETS.lock();
+    match EPOLLS.lock().get(epoll_id as usize) {
+        Some(x) if x.has_events(|y| socket_events(&sockets, y as usize) as u32) => POLLIN,
+        Some(_) => 0,
+        None => POLLNVAL,
+    }
+}
+
+/// Fetch the nth system call argument as a socket descriptor, returning the
+/// socket identifier.
+unsafe fn socket_arg(n: i32) -> Result<u32, NetError> {
+    let mut socket_id: i32 = 0;
+    if argsock(n, &mut socket_id) < 0 {
+        return Err(NetError::BadSocket);
+    }
+    Ok(socket_id as u32)
+}
+
+/// Fetch the nth system call argument as an epoll descriptor, returning the
+/// epoll instance identifier.
+unsafe fn epoll_arg(n: i32) -> Result<usize, NetError> {
+    let mut epoll_id: i32 = 0;
+    if argepoll(n, &mut epoll_id) < 0 {
+        return Err(NetError::BadSocket);
+    }
+    Ok(epoll_id as usize)
+}
+
+/// Fetch the nth system call argument as a pointer to a user buffer, with its
+/// length in the following argument.
+unsafe fn buffer_arg(n: i32) -> Result<&'static mut [u8], NetError> {
+    let mut len: i32 = 0;
+    argint(n + 1, &mut len);
+    if len < 0 {
+        return Err(NetError::InvalidArgument);
+    }
+
+    let mut data: *mut u8 = core::ptr::null_mut();
+    if argptr(n, &mut data as *const *mut u8 as _, len) < 0 {
+        return Err(NetError::Fault);
+    }
+    Ok(slice::from_raw_parts_mut(data, len as usize))
+}
+
+/// Check a user buffer of `len` bytes at `addr`.
+unsafe fn user_buffer(addr: u32, len: usize) -> Result<&'static mut [u8], NetError> {
+    let mut data: *mut u8 = core::ptr::null_mut();
+    if fetchptr(addr, &mut data as *const *mut u8 as _, len as i32) < 0 {
+        return Err(NetError::Fault);
+    }
+    Ok(slice::from_raw_parts_mut(data, len))
+}
+
+/// The bind system call.
+///
+/// Takes a `struct sockaddr_un` for unix domain sockets, otherwise a `struct
+/// sockaddr_in`.
+#[no_mangle]
+unsafe extern "C" fn sys_bind() -> i32 {
+    syscall_return((|| {
+        let socket_id = socket_arg(0)?;
+        match socket_type(socket_id)? {
+            SocketType::UnixStream | SocketType::UnixDgram => {
+                bind_unix(socket_id, UnixPath::from_user(1)?)?
+            }
+            _ => bind(socket_id, Peer::from_user(1)?)?,
+        }
+        Ok(0)
+    })())
+}
+
+/// The connect system call.
+///
+/// Takes a `struct sockaddr_un` for unix domain sockets, otherwise a `struct
+/// sockaddr_in`.
+#[no_mangle]
+unsafe extern "C" fn sys_connect() -> i32 {
+    syscall_return((|| {
+        let socket_id = socket_arg(0)?;
+        match socket_type(socket_id)? {
+            SocketType::UnixStream | SocketType::UnixDgram => {
+                connect_unix(socket_id, UnixPath::from_user(1)?)?
+            }
+            _ => connect(socket_id, Peer::from_user(1)?)?,
+        }
+        Ok(0)
+    })())
+}
+
+/// The getsockname system call.
+///
+/// Reports the local address and port a socket is bound to. Unbound sockets
+/// report the unspecified address and port 0, or an unnamed address for unix
+/// domain sockets.
+#[no_mangle]
+unsafe extern "C" fn sys_getsockname() -> i32 {
+    syscall_return((|| {
+        let socket_id = socket_arg(0)?;
+        let out = SockaddrOut::from_user(1)?.ok_or(NetError::Fault)?;
+
+        let sockets = SOCKETS.lock();
+        let socket = sockets.get(socket_id as usize).ok_or(NetError::BadSocket)?;
+        if socket.is_unix() {
+            out.write_unix(socket.path.as_ref());
+            return Ok(0);
+        }
+
+        // Connected sockets send from the local adaptor, report that rather
+        // than a wildcard.
+        let address = match socket.dest_protocol_address {
+            Some(_) => socket.local_address(),
+            None => socket.source_address.unwrap_or(Ipv4Addr::from(0u32)),
+        };
+        out.write(Peer {
+            address: address,
+            port: socket.source_port.unwrap_or(0),
+        });
+        Ok(0)
+    })())
+}
+
+/// The getpeername system call.
+///
+/// Reports the remote address and port of a connected socket, or the path of
+/// the remote of a connected unix domain socket.
+#[no_mangle]
+unsafe extern "C" fn sys_getpeername() -> i32 {
+    syscall_return((|| {
+        let socket_id = socket_arg(0)?;
+        let out = SockaddrOut::from_user(1)?.ok_or(NetError::Fault)?;
+
+        let sockets = SOCKETS.lock();
+        let socket = sockets.get(socket_id as usize).ok_or(NetError::BadSocket)?;
+        if socket.is_unix() {
+            let remote = socket.remote.ok_or(NetError::NotConnected)?;
+            let path = sockets.get(remote as usize).and_then(|x| x.path);
+            out.write_unix(path.as_ref());
+            return Ok(0);
+        }
+
+        match (socket.dest_protocol_address, socket.dest_port) {
+            (Some(address), Some(port)) => {
+                out.write(Peer { address, port });
+                Ok(0)
+            }
+            _ => Err(NetError::NotConnected),
+        }
+    })())
+}
+
+/// The setsockopt system call.
+///
+/// Sets an integer valued socket option from the `SOCKET_OPTIONS` registry.
+/// SO_REUSEADDR and SO_REUSEPORT take effect on the next bind(...),
+/// connect(...) or sendto(...) that binds the socket to a port.
+#[no_mangle]
+unsafe extern "C" fn sys_setsockopt() -> i32 {
+    syscall_return((|| {
+        let socket_id = socket_arg(0)?;
+
+        let mut level: i32 = 0;
+        argint(1, &mut level);
+
+        let mut name: i32 = 0;
+        argint(2, &mut name);
+
+        let mut len: i32 = 0;
+        argint(4, &mut len);
+
+        // Group membership options take a `struct ip_mreq` rather than an int.
+        if level == IPPROTO_IP && (name == IP_ADD_MEMBERSHIP || name == IP_DROP_MEMBERSHIP) {
+            if len < 8 {
+                return Err(NetError::InvalidArgument);
+            }
+            let mut mreq: *mut u8 = core::ptr::null_mut();
+            if argptr(3, &mut mreq as *const *mut u8 as _, 8) < 0 {
+                return Err(NetError::Fault);
+            }
+            let mreq = slice::from_raw_parts(mreq, 8);
+            set_membership(
+                socket_id,
+                Ipv4Addr::from_slice(&mreq[0..4]),
+                Ipv4Addr::from_slice(&mreq[4..8]),
+                name == IP_ADD_MEMBERSHIP,
+            )?;
+            update_groups();
+            return Ok(0);
+        }
+
+        if len != core::mem::size_of::<i32>() as i32 {
+            return Err(NetError::InvalidArgument);
+        }
+
+        let mut value: *mut i32 = core::ptr::null_mut();
+        if argptr(3, &mut value as *const *mut i32 as _, len) < 0 {
+            return Err(NetError::Fault);
+        }
+
+        let mut sockets = SOCKETS.lock();
+        let socket = sockets
+            .get_mut(socket_id as usize)
+            .ok_or(NetError::BadSocket)?;
+
+        let set = match SocketOption::find(level, name) {
+            Some(SocketOption { set: Some(x), .. }) => x,
+            _ => return Err(NetError::UnknownOption),
+        };
+        set(socket, *value)?;
+        Ok(0)
+    })())
+}
+
+/// The getsockopt system call.
+///
+/// Reads an integer valued socket option from the `SOCKET_OPTIONS` registry,
+/// setting `*optlen` to the size of the value.
+#[no_mangle]
+unsafe extern "C" fn sys_getsockopt() -> i32 {
+    syscall_return((|| {
+        let socket_id = socket_arg(0)?;
+
+        let mut level: i32 = 0;
+        argint(1, &mut level);
+
+        let mut name: i32 = 0;
+        argint(2, &mut name);
+
+        let mut len: *mut i32 = core::ptr::null_mut();
+        if argptr(4, &mut len as *const *mut i32 as _, 4) < 0 {
+            return Err(NetError::Fault);
+        }
+        if *len < core::mem::size_of::<i32>() as i32 {
+            return Err(NetError::InvalidArgument);
+        }
+
+        let mut value: *mut i32 = core::ptr::null_mut();
+        if argptr(3, &mut value as *const *mut i32 as _, 4) < 0 {
+            return Err(NetError::Fault);
+        }
+
+        let mut sockets = SOCKETS.lock();
+        let socket = sockets
+            .get_mut(socket_id as usize)
+            .ok_or(NetError::BadSocket)?;
+
+        let option = SocketOption::find(level, name).ok_or(NetError::UnknownOption)?;
+        *value = (option.get)(socket);
+        *len = core::mem::size_of::<i32>() as i32;
+        Ok(0)
+    })())
+}
+
+/// The listen system call.
+///
+/// Only unix domain stream sockets can listen for connections.
+#[no_mangle]
+unsafe extern "C" fn sys_listen() -> i32 {
+    syscall_return((|| {
+        let socket_id = socket_arg(0)?;
+
+        let mut backlog: i32 = 0;
+        argint(1, &mut backlog);
+
+        listen(socket_id, backlog)?;
+        Ok(0)
+    })())
+}
+
+/// The accept system call.
+///
+/// Returns a new file descriptor for the next connection to a listening
+/// socket, writing the path of the connecting socket to the optional `struct
+/// sockaddr_un` pointer.
+#[no_mangle]
+unsafe extern "C" fn sys_accept() -> i32 {
+    syscall_return((|| {
+        let socket_id = socket_arg(0)?;
+
+        // The address is checked before accepting, so no connection is lost to
+        // a bad pointer.
+        let out = SockaddrOut::from_user(1)?;
+
+        let (accepted, path) = accept(socket_id)?;
+        let fd = sockfdalloc(accepted as i32);
+        if fd < 0 {
+            return Err(NetError::TooManySockets);
+        }
+        if let Some(out) = out {
+            out.write_unix(path.as_ref());
+        }
+        Ok(fd as u32)
+    })())
+}
+
+/// The send system call.
+#[no_mangle]
+unsafe extern "C" fn sys_send() -> i32 {
+    syscall_return((|| {
+        let socket_id = socket_arg(0)?;
+        let data = buffer_arg(1)?;
+
+        let mut flags: i32 = 0;
+        argint(3, &mut flags);
+
+        send(socket_id, Gather::new(&[&data[..]]), flags as u32)
+    })())
+}
+
+/// The recv system call.
+#[no_mangle]
+unsafe extern "C" fn sys_recv() -> i32 {
+    syscall_return((|| {
+        let socket_id = socket_arg(0)?;
+        let data = buffer_arg(1)?;
+
+        let mut flags: i32 = 0;
+        argint(3, &mut flags);
+
+        recv(socket_id, &mut [data], flags as u32).map(|x| x.len)
+    })())
+}
+
+/// The sendto system call.
+///
+/// Sends a datagram to the address in the given `struct sockaddr_in`, or
+/// `struct sockaddr_un` for unix domain sockets, without connecting the
+/// socket. Frames sent on packet sockets carry their own destination, and
+/// unix domain stream sockets are always connected, so the address is ignored
+/// for both.
+#[no_mangle]
+unsafe extern "C" fn sys_sendto() -> i32 {
+    syscall_return((|| {
+        let socket_id = socket_arg(0)?;
+        let data = buffer_arg(1)?;
+
+        let mut flags: i32 = 0;
+        argint(3, &mut flags);
+
+        send_to_address(
+            socket_id,
+            Gather::new(&[&data[..]]),
+            flags as u32,
+            buffer_arg(4)?,
+        )
+    })())
+}
+
+/// The recvfrom system call.
+///
+/// Receives data as recv(...) does, additionally writing the address of the
+/// sender to the optional `struct sockaddr_in` pointer, or `struct
+/// sockaddr_un` for unix domain sockets.
+#[no_mangle]
+unsafe extern "C" fn sys_recvfrom() -> i32 {
+    syscall_return((|| {
+        let socket_id = socket_arg(0)?;
+        let data = buffer_arg(1)?;
+
+        let mut flags: i32 = 0;
+        argint(3, &mut flags);
+
+        // The address is checked before receiving, so no datagram is lost to a
+        // bad pointer.
+        let out = SockaddrOut::from_user(4)?;
+
+        let received = recv(socket_id, &mut [data], flags as u32)?;
+        if let (Some(out), Some(peer)) = (out, received.peer) {
+            out.write_sender(peer);
+        }
+        Ok(received.len)
+    })())
+}
+
+/// The sendmsg system call.
+///
+/// Sends the data gathered from the buffers of a `struct msghdr` as a single
+/// datagram to its optional address as sendto(...) does, or as send(...) does
+/// if it has none, with the same limits on its size. Ancillary data is not
+/// supported on send, the control buffer must be empty.
+#[no_mangle]
+unsafe extern "C" fn sys_sendmsg() -> i32 {
+    syscall_return((|| {
+        let socket_id = socket_arg(0)?;
+        let msg = Msghdr::from_user(1)?;
+
+        let mut flags: i32 = 0;
+        argint(2, &mut flags);
+
+        if msg.controllen != 0 {
+            return Err(NetError::InvalidArgument);
+        }
+
+        let buffers = msg.buffers()?;
+        let buffers: Vec<&[u8]> = buffers.iter().map(|x| &x[..]).collect();
+        let data = Gather::new(&buffers);
+
+        match msg.name {
+            0 => send(socket_id, data, flags as u32),
+            x => send_to_address(
+                socket_id,
+                data,
+                flags as u32,
+                user_buffer(x, msg.namelen as usize)?,
+            ),
+        }
+    })())
+}
+
+/// The recvmsg system call.
+///
+/// Receives data as recv(...) does, scattering it across the buffers of a
+/// `struct msghdr`. The address of the sender is written to the optional name
+/// of the message, as recvfrom(...) does, and ancillary data enabled by
+/// SO_TIMESTAMP, IP_PKTINFO and IP_RECVTTL to its control buffer.
+/// Sets MSG_TRUNC in the flags of the message if part of a datagram was
+/// discarded, and MSG_CTRUNC if ancillary data did not fit.
+#[no_mangle]
+unsafe extern "C" fn sys_recvmsg() -> i32 {
+    syscall_return((|| {
+        let socket_id = socket_arg(0)?;
+        let msg = Msghdr::from_user(1)?;
+
+        let mut flags: i32 = 0;
+        argint(2, &mut flags);
+
+        // Everything is checked before receiving, so no datagram is lost to a
+        // bad pointer.
+        let mut buffers = msg.buffers()?;
+        let out = match msg.name {
+            0 => None,
+            x => Some(SockaddrOut {
+                addr: user_buffer(x, (msg.namelen as usize).min(SOCKADDR_UN_SIZE))?.as_mut_ptr(),
+                len: &mut msg.namelen,
+            }),
+        };
+        let mut control = ControlWriter {
+            buf: match msg.control {
+                0 => &mut [],
+                x => user_buffer(x, msg.controllen as usize)?,
+            },
+            len: 0,
+            truncated: false,
+        };
+
+        let received = recv(socket_id, &mut buffers, flags as u32)?;
+
+        if let (Some(out), Some(peer)) = (&out, received.peer) {
+            out.write_sender(peer);
+        }
+
+        let (timestamps, packet_info, receive_ttl, local_address) = {
+            let sockets = SOCKETS.lock();
+            let socket = sockets.get(socket_id as usize).ok_or(NetError::BadSocket)?;
+            (
+                socket.timestamps,
+                socket.packet_info,
+                socket.receive_ttl,
+                socket.local_address(),
+            )
+        };
+        if timestamps {
+            control.push(SOL_SOCKET, SO_TIMESTAMP, &received.timestamp.to_ne_bytes());
+        }
+        if let Some(info) = received.info {
+            if packet_info {
+                let pktinfo = InPktinfo {
+                    ifindex: 1,
+                    spec_dst: local_address.as_bytes(),
+                    addr: info.destination.as_bytes(),
+                };
+                let pktinfo = slice::from_raw_parts(
+                    &pktinfo as *const InPktinfo as *const u8,
+                    core::mem::size_of::<InPktinfo>(),
+                );
+                control.push(IPPROTO_IP, IP_PKTINFO, pktinfo);
+            }
+            if receive_ttl {
+                control.push(
+                    IPPROTO_IP,
+                    IP_TTL,
+                    &(info.time_to_live as i32).to_ne_bytes(),
+                );
+            }
+        }
+
+        msg.controllen = control.len as u32;
+        msg.flags = 0;
+        if received.truncated {
+            msg.flags |= MSG_TRUNC;
+        }
+        if control.truncated {
+            msg.flags |= MSG_CTRUNC;
+        }
+        Ok(received.len)
+    })())
+}
+
+/// The sendfile system call.
+///
+/// Sends `count` bytes of a regular file, starting at `offset`, on a connected
+/// socket without copying them through user space. The offset of the file
+/// descriptor is left alone. Returns the number of bytes sent, fewer than
+/// `count` if the end of the file was reached first.
+#[no_mangle]
+unsafe extern "C" fn sys_sendfile() -> i32 {
+    syscall_return((|| {
+        let socket_id = socket_arg(0)?;
+
+        let mut file: *mut c_void = core::ptr::null_mut();
+        if argfile(1, &mut file) < 0 {
+            return Err(NetError::BadSocket);
+        }
+
+        let mut offset: i32 = 0;
+        argint(2, &mut offset);
+        let mut count: i32 = 0;
+        argint(3, &mut count);
+        if count < 0 {
+            return Err(NetError::InvalidArgument);
+        }
+
+        send_file(socket_id, file, offset as u32, count as usize)
+    })())
+}
+
+/// The epoll_create system call.
+///
+/// Creates an epoll instance with no sockets registered and returns a
+/// descriptor for it. `size` is ignored, but must be positive as on other
+/// systems.
+#[no_mangle]
+unsafe extern "C" fn sys_epoll_create() -> i32 {
+    syscall_return((|| {
+        let mut size: i32 = 0;
+        argint(0, &mut size);
+        if size <= 0 {
+            return Err(NetError::InvalidArgument);
+        }
+
+        let epoll_id = EPOLLS
+            .lock()
+            .insert(Box::new(Epoll::new()))
+            .ok_or(NetError::SocketTableFull)?;
+        // The instance is released again if no descriptor is free.
+        let fd = epollfdalloc(epoll_id as i32);
+        if fd < 0 {
+            return Err(NetError::TooManySockets);
+        }
+        Ok(fd as u32)
+    })())
+}
+
+/// The epoll_ctl system call.
+///
+/// Registers a socket with an epoll instance, changes the events it is
+/// watched for or unregisters it, with `op` one of EPOLL_CTL_ADD,
+/// EPOLL_CTL_MOD or EPOLL_CTL_DEL. Takes a `struct epoll_event` with the
+/// events to watch for and the data to report them with, ignored by
+/// EPOLL_CTL_DEL.
+#[no_mangle]
+unsafe extern "C" fn sys_epoll_ctl() -> i32 {
+    syscall_return((|| {
+        let epoll_id = epoll_arg(0)?;
+        let mut op: i32 = 0;
+        argint(1, &mut op);
+        let socket_id = socket_arg(2)?;
+
+        let (events, data) = if op == EPOLL_CTL_DEL {
+            (0, 0)
+        } else {
+            let mut event: *mut u8 = core::ptr::null_mut();
+            if argptr(3, &mut event as *const *mut u8 as _, 8) < 0 {
+                return Err(NetError::Fault);
+            }
+            let event = slice::from_raw_parts(event, 8);
+            (
+                u32::from_ne_bytes([event[0], event[1], event[2], event[3]]),
+                u32::from_ne_bytes([event[4], event[5], event[6], event[7]]),
+            )
+        };
+
+        epoll_control(epoll_id, op, socket_id as usize, events, data)?;
+        Ok(0)
+    })())
+}
+
+/// The epoll_wait system call.
+///
+/// Waits for registered sockets to have events to report, then writes up to
+/// `maxevents` of them to the `struct epoll_event` array and returns how many
+/// were written. The timeout is in clock ticks, a negative timeout waits
+/// forever and a timeout of zero returns straight away.
+#[no_mangle]
+unsafe extern "C" fn sys_epoll_wait() -> i32 {
+    syscall_return((|| {
+        let epoll_id = epoll_arg(0)?;
+        let mut max: i32 = 0;
+        argint(2, &mut max);
+        if max <= 0 {
+            return Err(NetError::InvalidArgument);
+        }
+        let mut timeout: i32 = 0;
+        argint(3, &mut timeout);
+
+        let mut out: *mut u8 = core::ptr::null_mut();
+        if argptr(1, &mut out as *const *mut u8 as _, max.saturating_mul(8)) < 0 {
+            return Err(NetError::Fault);
+        }
+        // No more sockets than exist can have events.
+        let max = (max as usize).min(MAX_SOCKETS);
+        let out = slice::from_raw_parts_mut(out, max * 8);
+
+        let events = epoll_wait(epoll_id, max, timeout)?;
+        for (event, (events, data)) in out.chunks_mut(8).zip(events.iter()) {
+            event[0..4].copy_from_slice(&events.to_ne_bytes());
+            event[4..8].copy_from_slice(&data.to_ne_bytes());
+        }
+        Ok(events.len() as u32)
+    })())
+}
+
+/// The shutdown system call.
+///
+/// Disables further sends and receives on the socket. The socket itself is
+/// released by close(...) once no descriptors refer to it.
+#[no_mangle]
+unsafe extern "C" fn sys_shutdown() -> i32 {
+    syscall_return((|| {
+        let socket_id = socket_arg(0)?;
+        shutdown_socket(socket_id)?;
+        Ok(0)
+    })())
+}
+
+/// Create a new socket of the specified domain, owned by the process `pid`,
+/// and return the socket identifer.
+///
+/// Fails with `NetError::TooManySockets` if the process already holds
+/// `MAX_SOCKETS_PER_PROCESS` sockets it created, or
+/// `NetError::SocketTableFull` if the system holds `MAX_SOCKETS`.
+fn create_socket(domain: SocketType, pid: u32, nonblocking: bool) -> Result<u32, NetError> {
+    let mut sockets = SOCKETS.lock();
+    // Inherited sockets count against the process that created them only, and
+    // no longer count once it has exited.
+    let held = sockets
+        .values()
+        .filter(|x| x.creator == pid && x.owners.contains(&pid))
+        .count();
+    if held >= MAX_SOCKETS_PER_PROCESS {
+        return Err(NetError::TooManySockets);
+    }
+
+    let socket_id = sockets
+        .insert(Box::new(Socket::new(domain, pid, vec![pid], nonblocking)))
+        .ok_or(NetError::SocketTableFull)?;
+    match domain {
+        SocketType::Raw(x) => DEMUX.lock().insert_raw(x, socket_id),
+        SocketType::Packet(ETH_P_ALL) => DEMUX.lock().insert_frame(None, socket_id),
+        SocketType::Packet(x) => DEMUX.lock().insert_frame(Some(x), socket_id),
+        _ => (),
+    }
+    Ok(socket_id as u32)
+}
+
+/// Join or leave a multicast group on a UDP socket.
+///
+/// The group is joined on the local adaptor, `interface` must be unspecified
+/// or its address. Joining a group the socket is already in fails with
+/// `NetError::AddressInUse`, and leaving one it is not in with
+/// `NetError::AddressNotAvailable`. IGMP messages for the group are left to
+/// update_groups(...).
+fn set_membership(
+    socket_id: u32,
+    group: Ipv4Addr,
+    interface: Ipv4Addr,
+    join: bool,
+) -> Result<(), NetError> {
+    if !group.is_multicast() {
+        return Err(NetError::InvalidArgument);
+    }
+    if !interface.is_unspecified() && interface != Ipv4Addr::from(LOCAL_ADDRESS) {
+        return Err(NetError::AddressNotAvailable);
+    }
+
+    let mut sockets = SOCKETS.lock();
+    let socket = match sockets.get_mut(socket_id as usize) {
+        Some(x) if x.r#type == SocketType::UDP => x,
+        Some(_) => return Err(NetError::OperationNotSupported),
+        None => return Err(NetError::BadSocket),
+    };
+
+    let index = socket.groups.iter().position(|x| *x == group);
+    match (join, index) {
+        (true, Some(_)) => return Err(NetError::AddressInUse),
+        (true, None) if socket.groups.len() >= MAX_MEMBERSHIPS => return Err(NetError::NoBuffers),
+        (true, None) => {
+            socket.groups.push(group);
+            GROUPS.lock().join(group);
+        }
+        (false, Some(x)) => {
+            socket.groups.remove(x);
+            GROUPS.lock().leave(group);
+        }
+        (false, None) => return Err(NetError::AddressNotAvailable),
+    }
+    Ok(())
+}
+
+/// Send the IGMP reports and leaves that are due, and reprogram the multicast
+/// filter of the device if groups were joined or left.
+///
+/// Called on every clock tick, and after a socket joins or leaves a group so
+/// the first report goes out straight away. Must not be called with the
+/// `SOCKETS` lock held.
+fn update_groups() {
+    let mut device = NETWORK_DEVICE.lock();
+    let device: &mut Box<dyn NetworkDevice> = match *device {
+        Some(ref mut x) => x,
+        None => return,
+    };
+
+    let (reports, leaves, filter) = {
+        let mut groups = GROUPS.lock();
+        let (reports, leaves) = groups.tick();
+        (reports, leaves, groups.filter())
+    };
+
+    // Messages dropped for want of transmit buffers are not retried, queriers
+    // ask again.
+    for group in reports {
+        let _ = send_igmp(device, igmp::Type::V2MembershipReport, group, group);
+    }
+    for group in leaves {
+        let _ = send_igmp(
+            device,
+            igmp::Type::LeaveGroup,
+            group,
+            Ipv4Addr::from(ALL_ROUTERS_GROUP),
+        );
+    }
+    if let Some(x) = filter {
+        device.set_multicast_filter(&x);
+    }
+}
+
+/// Transmit an IGMP message about `group` to `dest`.
+///
+/// IGMP messages never leave the local network, so they are sent with a time
+/// to live of 1, and carry the router alert option.
+///
+/// RFC2236 Section 2
+fn send_igmp(
+    device: &mut Box<dyn NetworkDevice>,
+    r#type: igmp::Type,
+    group: Ipv4Addr,
+    dest: Ipv4Addr,
+) -> Result<(), NetError> {
+    let mut packet = PacketBuffer::from_payload(&IgmpPacket::new(r#type, group));
+
+    let mut ip_packet = Ipv4Packet::new(
+        0,
+        0,
+        0,
+        0,
+        true,
+        false,
+        0,
+        1,
+        Protocol::IGMP,
+        device.protocol_address(),
+        dest,
+    );
+    ip_packet.set_router_alert();
+    ip_packet.set_total_length((packet.len() + ip_packet.header_size()) as u16);
+    packet.serialize(&ip_packet);
+
+    let ethernet_frame = EthernetFrame::new(
+        EthernetAddress::from_multicast(dest),
+        device.hardware_address(),
+        Ethertype::IPV4,
+    );
+    packet.serialize(&ethernet_frame);
+    device.send(packet)
+}
+
+/// Send data to the socket address `addr` copied from user space, as
+/// sendto(...) does.
+fn send_to_address(
+    socket_id: u32,
+    data: Gather<&[u8]>,
+    flags: u32,
+    addr: &[u8],
+) -> Result<u32, NetError> {
+    match socket_type(socket_id)? {
+        SocketType::Packet(_) | SocketType::UnixStream => send(socket_id, data, flags),
+        SocketType::UnixDgram => {
+            let remote = find_unix(&UnixPath::from_sockaddr(addr)?, SocketType::UnixDgram)?;
+            send_unix(socket_id, remote, data, flags)
+        }
+        _ => send_to(socket_id, data, flags, Peer::from_sockaddr(addr)?),
+    }
+}
+
+/// Create a pair of anonymous unix domain sockets of `r#type`, owned by the
+/// process `pid` and connected to each other.
+fn create_pair(r#type: SocketType, pid: u32, nonblocking: bool) -> Result<(u32, u32), NetError> {
+    let first = create_socket(r#type, pid, nonblocking)?;
+    let second = match create_socket(r#type, pid, nonblocking) {
+        Ok(x) => x,
+        Err(x) => {
+            let _ = close_socket(first);
+            return Err(x);
+        }
+    };
+
+    let mut sockets = SOCKETS.lock();
+    for (socket_id, remote) in [(first, second), (second, first)] {
+        if let Some(x) = sockets.get_mut(socket_id as usize) {
+            x.remote = Some(remote);
+        }
+    }
+    Ok((first, second))
+}
+
+/// The type of a socket.
+fn socket_type(socket_id: u32) -> Result<SocketType, NetError> {
+    let sockets = SOCKETS.lock();
+    match sockets.get(socket_id as usize) {
+        Some(x) => Ok(x.r#type),
+        None => Err(NetError::BadSocket),
+    }
+}
+
+/// Bind a socket to a local address and port.
+///
+/// Binding the unspecified address receives packets sent to any local address,
+/// and binding port 0 selects a free ephemeral port. Fails with
+/// `NetError::InvalidArgument` if the socket is already bound.
+fn bind(socket_id: u32, source: Peer) -> Result<(), NetError> {
+    let mut sockets = SOCKETS.lock();
+    let socket = match sockets.get_mut(socket_id as usize) {
+        Some(x) if x.is_packet() => return Err(NetError::OperationNotSupported),
+        Some(x) if x.source_port.is_none() => x,
+        Some(_) => return Err(NetError::InvalidArgument),
+        None => return Err(NetError::BadSocket),
+    };
+
+    socket.bind_to(socket_id as usize, source.address, source.port)
+}
+
+/// Connect to a remote socket.
+///
+/// Resolves the hardware address of the remote with ARP. Blocking sockets
+/// sleep until the address resolves, ARP gives up or the SO_SNDTIMEO timeout
+/// passes. Non-blocking sockets fail with `NetError::InProgress` while the
+/// address is still resolving, completing the connection in the background;
+/// poll(...) reports POLLOUT once it completes and SO_ERROR reports any
+/// failure.
+fn connect(socket_id: u32, dest: Peer) -> Result<(), NetError> {
+    let (timeout, nonblocking) = check_destination(socket_id, dest.address)?;
+
+    // Populate the details of the remote, binding the Socket to a new ephemeral
+    // port if not already bound. From now on only packets from the remote are
+    // delivered to the socket.
+    {
+        let mut sockets = SOCKETS.lock();
+        let socket = match sockets.get_mut(socket_id as usize) {
+            Some(x) if !x.resolving => x,
+            Some(_) => return Err(NetError::Already),
+            None => return Err(NetError::BadSocket),
+        };
+        socket.bind_ephemeral(socket_id as usize)?;
+        socket.error = None;
+        socket.dest_port = Some(dest.port);
+        socket.dest_protocol_address = Some(dest.address);
+        socket.dest_hardware_address = None;
+        socket.resolving = true;
+        if socket.source_port.is_some() {
+            DEMUX
+                .lock()
+                .insert(socket.protocol(), socket.endpoint(socket_id as usize));
+        }
+    }
+
+    // Look up the desination hardware address from the cache or start resolving
+    // it, in which case `resolved` completes the connection. A socket that
+    // cannot start resolving is left unconnected.
+    match start_resolve(dest.address) {
+        Ok(Some(x)) => resolved(dest.address, Some(x)),
+        Ok(None) => (),
+        Err(x) => {
+            if let Some(socket) = SOCKETS.lock().get_mut(socket_id as usize) {
+                socket.disconnect(socket_id as usize);
+            }
+            return Err(x);
+        }
+    }
+
+    let deadline = timeout.map(|x| now().wrapping_add(x));
+    let mut sockets = SOCKETS.lock();
+    loop {
+        let socket = match sockets.get_mut(socket_id as usize) {
+            Some(x) => x,
+            None => return Err(NetError::BadSocket),
+        };
+
+        if !socket.resolving {
+            return match socket.dest_hardware_address {
+                Some(_) => Ok(()),
+                // The failure is reported here rather than by SO_ERROR.
+                None => Err(socket.error.take().unwrap_or(NetError::HostUnreachable)),
+            };
+        }
+
+        if nonblocking {
+            return Err(NetError::InProgress);
+        }
+
+        if unsafe { killed() } != 0 {
+            socket.disconnect(socket_id as usize);
+            return Err(NetError::Interrupted);
+        }
+        if deadline.map_or(false, expired) {
+            socket.disconnect(socket_id as usize);
+            return Err(NetError::TimedOut);
+        }
+
+        let chan = socket.channel();
+        if let Some(x) = deadline {
+            wake_at(x, chan);
+        }
+        sockets = sockets.sleep(chan);
+    }
+}
+
+/// Is `address` the limited broadcast address, or the directed broadcast
+/// address of the local subnet?
+fn is_broadcast(address: Ipv4Addr) -> bool {
+    address.is_broadcast() || address == Ipv4Addr::from(LOCAL_ADDRESS | !SUBNET_MASK)
+}
+
+/// Check that a socket may send to a destination.
+///
+/// Sending to a broadcast address requires SO_BROADCAST. Returns the number
+/// of ticks to wait for the destination to resolve, from SO_SNDTIMEO, and
+/// whether the socket is non-blocking.
+fn check_destination(
+    socket_id: u32,
+    dest_protocol_address: Ipv4Addr,
+) -> Result<(Option<u32>, bool), NetError> {
+    let sockets = SOCKETS.lock();
+    let socket = match sockets.get(socket_id as usize) {
+        Some(x) if !x.shutdown => x,
+        Some(_) => return Err(NetError::Shutdown),
+        None => return Err(NetError::BadSocket),
+    };
+    if socket.is_packet() {
+        return Err(NetError::OperationNotSupported);
+    }
+
+    if is_broadcast(dest_protocol_address) && !socket.broadcast {
+        return Err(NetError::AccessDenied);
+    }
+
+    let timeout = match socket.sndtimeo {
+        0 => None,
+        x => Some(x),
+    };
+    Ok((timeout, socket.nonblocking))
+}
+
+/// The channel processes sleep on while waiting for ARP replies.
+fn arp_channel() -> *const c_void {
+    &ARP_CACHE as *const Spinlock<ArpCache> as *const c_void
+}
+
+/// Look up the hardware address of a host on the local network, making an ARP
+/// request if it is not in the cache and no request is outstanding.
+///
+/// Broadcast and multicast addresses map to their hardware addresses without
+/// ARP. Returns `None` if the address is being resolved. Must not be called
+/// with the `SOCKETS` lock held.
+fn start_resolve(dest_protocol_address: Ipv4Addr) -> Result<Option<EthernetAddress>, NetError> {
+    if is_broadcast(dest_protocol_address) {
+        return Ok(Some(EthernetAddress::BROADCAST));
+    }
+    if dest_protocol_address.is_multicast() {
+        return Ok(Some(EthernetAddress::from_multicast(dest_protocol_address)));
+    }
+
+    {
+        let mut arp_cache = ARP_CACHE.lock();
+        if let Some(x) = arp_cache.hardware_address(&dest_protocol_address) {
+            return Ok(Some(x));
+        }
+        if !arp_cache.request(&dest_protocol_address) {
+            return Ok(None);
+        }
+    }
+
+    let mut device = NETWORK_DEVICE.lock();
+    let device: &mut Box<dyn NetworkDevice> = match *device {
+        Some(ref mut x) => x,
+        None => return Err(NetError::NetworkDown),
+    };
+    ArpCache::resolve(&dest_protocol_address, device)?;
+    Ok(None)
+}
+
+/// Resolve the hardware address of a host on the local network.
+///
+/// Returns the address from the ARP cache if present, otherwise makes an ARP
+/// request and sleeps until the reply arrives, ARP gives up or `timeout`
+/// ticks pass. Non-blocking callers fail with `NetError::WouldBlock` rather
+/// than sleep. Must not be called with the `SOCKETS` lock held.
+fn resolve(
+    dest_protocol_address: Ipv4Addr,
+    timeout: Option<u32>,
+    nonblocking: bool,
+) -> Result<EthernetAddress, NetError> {
+    if let Some(x) = start_resolve(dest_protocol_address)? {
+        return Ok(x);
+    }
+    if nonblocking {
+        return Err(NetError::WouldBlock);
+    }
+
+    let deadline = timeout.map(|x| now().wrapping_add(x));
+    let mut arp_cache = ARP_CACHE.lock();
+    loop {
+        if let Some(x) = arp_cache.hardware_address(&dest_protocol_address) {
+            return Ok(x);
+        }
+
+        if !arp_cache.is_pending(&dest_protocol_address) {
+            return Err(NetError::HostUnreachable);
+        }
+        if unsafe { killed() } != 0 {
+            return Err(NetError::Interrupted);
+        }
+        if deadline.map_or(false, expired) {
+            return Err(NetError::TimedOut);
+        }
+
+        if let Some(x) = deadline {
+            wake_at(x, arp_channel());
+        }
+        arp_cache = arp_cache.sleep(arp_channel());
+    }
+}
+
+/// Complete connect(...) on the sockets waiting for an address to resolve.
+///
+/// Called with the hardware address when an ARP reply arrives, or `None` when
+/// ARP gives up on the address.
+fn resolved(dest_protocol_address: Ipv4Addr, dest_hardware_address: Option<EthernetAddress>) {
+    let mut sockets = SOCKETS.lock();
+    let mut woken = false;
+    for (socket_id, socket) in sockets.iter_mut() {
+        if !socket.resolving || socket.dest_protocol_address != Some(dest_protocol_address) {
+            continue;
+        }
+
+        match dest_hardware_address {
+            Some(x) => {
+                socket.dest_hardware_address = Some(x);
+                socket.resolving = false;
+            }
+            None => {
+                socket.disconnect(socket_id);
+                socket.error = Some(NetError::HostUnreachable);
+            }
+        }
+        unsafe { wakeup(socket.channel()) };
+        socket.notify(socket_id);
+        woken = true;
+    }
+
+    if woken {
+        unsafe { pollwakeup() };
+    }
+}
+
+/// Retry outstanding ARP requests, failing any that have run out of attempts.
+fn retry_arp() {
+    let (retry, failed) = ARP_CACHE.lock().tick();
+
+    if !retry.is_empty() {
+        let mut device = NETWORK_DEVICE.lock();
+        if let Some(ref mut device) = *device {
+            // A request dropped for want of transmit buffers is retried on
+            // the next backoff.
+            for address in retry.iter() {
+                let _ = ArpCache::resolve(address, device);
+            }
+        }
+    }
+
+    if !failed.is_empty() {
+        for address in failed {
+            resolved(address, None);
+        }
+        unsafe { wakeup(arp_channel()) };
+    }
+}
+
+/// Send data on a connected socket.
+///
+/// Sends never block, so MSG_DONTWAIT is accepted but has no effect. Fails
+/// with `NetError::MessageTooLong` if `data` is larger than the SO_SNDBUF of
+/// the socket, or `NetError::NotConnected` if the socket is not connected or
+/// its connect(...) is still in progress. A pending error on the socket, such
+/// as `NetError::ConnectionRefused`, is reported and cleared instead of
+/// sending.
+fn send(socket_id: u32, data: Gather<&[u8]>, flags: u32) -> Result<u32, NetError> {
+    if flags & !MSG_DONTWAIT != 0 {
+        return Err(NetError::InvalidArgument);
+    }
+
+    let mut sockets = SOCKETS.lock();
+    let socket = match sockets.get_mut(socket_id as usize) {
+        Some(x) if !x.shutdown => x,
+        Some(_) => return Err(NetError::Shutdown),
+        None => return Err(NetError::BadSocket),
+    };
+    if let Some(x) = socket.error.take() {
+        return Err(x);
+    }
+
+    // Unix domain sockets queue data directly on their remote.
+    if socket.is_unix() {
+        let remote = match socket.remote {
+            Some(_) if socket.hangup => return Err(NetError::Shutdown),
+            Some(x) => x,
+            None => return Err(NetError::NotConnected),
+        };
+        drop(sockets);
+        return send_unix(socket_id, remote, data, flags);
+    }
+
+    if data.len() > socket.sndbuf as usize {
+        return Err(NetError::MessageTooLong);
+    }
+
+    // Frames from packet sockets carry their own destination.
+    if socket.is_packet() {
+        drop(sockets);
+        return send_frame_raw(data);
+    }
+
+    // The socket must have been set up with connect(...).
+    let (dest_protocol_address, dest_hardware_address, dest_port) = match (
+        socket.dest_protocol_address,
+        socket.dest_hardware_address,
+        socket.dest_port,
+    ) {
+        (Some(a), Some(h), Some(p)) => (a, h, p),
+        _ => return Err(NetError::NotConnected),
+    };
+    let outgoing = socket.outgoing();
+    drop(sockets);
+
+    let dest = Peer {
+        address: dest_protocol_address,
+        port: dest_port,
+    };
+    transmit(&outgoing, dest, dest_hardware_address, data)
+}
+
+/// Send data on a socket to an explicit destination.
+///
+/// Sleeps while the destination resolves unless the socket is non-blocking or
+/// MSG_DONTWAIT is set, in which case the send fails while resolution
+/// continues in the background. The socket does not need to be connected.
+/// Unbound sockets are bound to the address of the local adaptor and an
+/// ephemeral port, as with connect(...).
+fn send_to(socket_id: u32, data: Gather<&[u8]>, flags: u32, dest: Peer) -> Result<u32, NetError> {
+    if flags & !MSG_DONTWAIT != 0 {
+        return Err(NetError::InvalidArgument);
+    }
+
+    let (timeout, nonblocking) = check_destination(socket_id, dest.address)?;
+    let nonblocking = nonblocking || flags & MSG_DONTWAIT != 0;
+    let dest_hardware_address = resolve(dest.address, timeout, nonblocking)?;
+
+    let mut sockets = SOCKETS.lock();
+    let socket = match sockets.get_mut(socket_id as usize) {
+        Some(x) if !x.shutdown => x,
+        Some(_) => return Err(NetError::Shutdown),
+        None => return Err(NetError::BadSocket),
+    };
+    if data.len() > socket.sndbuf as usize {
+        return Err(NetError::MessageTooLong);
+    }
+    socket.bind_ephemeral(socket_id as usize)?;
+    let outgoing = socket.outgoing();
+    drop(sockets);
+
+    transmit(&outgoing, dest, dest_hardware_address, data)
+}
+
+/// Send data from a socket using the settings captured in `outgoing`.
+///
+/// Must not be called with the `SOCKETS` lock held.
+fn transmit(
+    outgoing: &Outgoing,
+    dest: Peer,
+    dest_hardware_address: EthernetAddress,
+    data: Gather<&[u8]>,
+) -> Result<u32, NetError> {
+    match outgoing.r#type {
+        SocketType::Raw(protocol) => {
+            send_raw(outgoing, protocol, dest, dest_hardware_address, data)
+        }
+        _ => send_udp(outgoing, dest, dest_hardware_address, data),
+    }
+}
+
+/// Encapsulate and transmit a UDP datagram.
+///
+/// Datagrams of up to 65507 bytes are sent, fragmented if they do not fit in
+/// a single frame, returning the number of bytes sent. Must not be called with
+/// the `SOCKETS` lock held.
+fn send_udp(
+    outgoing: &Outgoing,
+    dest: Peer,
+    dest_hardware_address: EthernetAddress,
+    data: Gather<&[u8]>,
+) -> Result<u32, NetError> {
+    if data.len() > MAX_UDP_PAYLOAD {
+        return Err(NetError::MessageTooLong);
+    }
+
+    let header = UdpPacket::header(outgoing.source.port, dest.port, data.len());
+    send_ip(
+        outgoing,
+        Protocol::UDP,
+        dest.address,
+        dest_hardware_address,
+        &header,
+        data,
+    )?;
+    Ok(data.len() as u32)
+}
+
+/// Transmit a packet from a raw socket.
+///
+/// Without IP_HDRINCL, `data` is the payload of a packet built for the
+/// protocol of the socket, fragmented if it does not fit in a single frame.
+/// With it, `data` is a whole IPv4 packet whose total length and checksum are
+/// filled in, along with its source address if left unspecified, and fails
+/// with `NetError::MessageTooLong` if the packet would not fit in a single
+/// frame. Must not be called with the `SOCKETS` lock held.
+fn send_raw(
+    outgoing: &Outgoing,
+    protocol: Protocol,
+    dest: Peer,
+    dest_hardware_address: EthernetAddress,
+    data: Gather<&[u8]>,
+) -> Result<u32, NetError> {
+    if !outgoing.header_included {
+        send_ip(
+            outgoing,
+            protocol,
+            dest.address,
+            dest_hardware_address,
+            &[],
+            data,
+        )?;
+        return Ok(data.len() as u32);
+    }
+
+    if data.len() > MTU {
+        return Err(NetError::MessageTooLong);
+    }
+    let data = data.to_vec();
+    // Headers with options are not supported.
+    let mut ip_packet = match Ipv4Packet::from_slice(&data) {
+        Ok(x) if x.header_size() == 20 => x,
+        _ => return Err(NetError::InvalidArgument),
+    };
+    ip_packet.set_total_length(data.len() as u16);
+    if ip_packet.source().is_unspecified() {
+        ip_packet.set_source(outgoing.source.address);
+    }
+    let mut packet = PacketBuffer::from_payload(&data[20..]);
+    packet.serialize(&ip_packet);
+
+    send_frame(packet, dest_hardware_address)?;
+    Ok(data.len() as u32)
+}
+
+/// Encapsulate a transport `header` and `data` in IPv4 packets and transmit
+/// them.
+///
+/// The header is kept apart from the data so large payloads are sent without
+/// first being copied into a single buffer. Packets larger than the MTU are
+/// split into fragments, unless IP_MTU_DISCOVER is IP_PMTUDISC_DO, in which
+/// case the send fails with `NetError::MessageTooLong`. Unfragmented packets
+/// are sent with the don't fragment flag unless IP_MTU_DISCOVER is
+/// IP_PMTUDISC_DONT. Fragments already sent are not recalled if a later one
+/// fails. Must not be called with the `SOCKETS` lock held.
+fn send_ip(
+    outgoing: &Outgoing,
+    protocol: Protocol,
+    dest: Ipv4Addr,
+    dest_hardware_address: EthernetAddress,
+    header: &[u8],
+    data: Gather<&[u8]>,
+) -> Result<(), NetError> {
+    let len = header.len() + data.len();
+    if len + 20 > MAX_PACKET_SIZE {
+        return Err(NetError::MessageTooLong);
+    }
+
+    let fits = len + 20 <= MTU;
+    let dont_fragment = match outgoing.mtu_discover {
+        IP_PMTUDISC_DO if !fits => return Err(NetError::MessageTooLong),
+        IP_PMTUDISC_DONT => false,
+        _ => fits,
+    };
+    let identification = IDENTIFICATION.fetch_add(1, Ordering::Relaxed);
+
+    // The transport header travels in the first fragment.
+    for fragment in ip::fragments(len, MTU) {
+        let (start, end) = (fragment.start, fragment.end);
+        let mut packet = PacketBuffer::from_payload(&data.range(
+            start.saturating_sub(header.len()),
+            end.saturating_sub(header.len()),
+        ));
+        packet.serialize(&header[start.min(header.len())..end.min(header.len())]);
+
+        let ip_packet = Ipv4Packet::new(
+            outgoing.tos >> 2,
+            outgoing.tos & 0x3,
+            (end - start + 20) as u16,
+            identification,
+            dont_fragment,
+            fragment.more,
+            fragment.offset(),
+            outgoing.ttl,
+            protocol,
+            outgoing.source.address,
+            dest,
+        );
+        packet.serialize(&ip_packet);
+        send_frame(packet, dest_hardware_address)?;
+    }
+    Ok(())
+}
+
+/// Encapsulate a transport segment built in place in `packet` in an IPv4
+/// packet and transmit it.
+///
+/// Unlike send_ip(...) the payload is not copied, but must fit in a single
+/// frame. The packet is sent with the don't fragment flag unless
+/// IP_MTU_DISCOVER is IP_PMTUDISC_DONT. Must not be called with the `SOCKETS`
+/// lock held.
+fn send_ip_buffer(
+    outgoing: &Outgoing,
+    protocol: Protocol,
+    dest: Ipv4Addr,
+    dest_hardware_address: EthernetAddress,
+    mut packet: PacketBuffer,
+) -> Result<(), NetError> {
+    if packet.len() + 20 > MTU {
+        return Err(NetError::MessageTooLong);
+    }
+
+    let ip_packet = Ipv4Packet::new(
+        outgoing.tos >> 2,
+        outgoing.tos & 0x3,
+        (packet.len() + 20) as u16,
+        IDENTIFICATION.fetch_add(1, Ordering::Relaxed),
+        outgoing.mtu_discover != IP_PMTUDISC_DONT,
+        false,
+        0,
+        outgoing.ttl,
+        protocol,
+        outgoing.source.address,
+        dest,
+    );
+    packet.serialize(&ip_packet);
+    send_frame(packet, dest_hardware_address)
+}
+
+/// Transmit a whole ethernet frame built in user space.
+///
+/// The frame must hold at least an ethernet header and fit the MTU. Must not
+/// be called with the `SOCKETS` lock held.
+fn send_frame_raw(data: Gather<&[u8]>) -> Result<u32, NetError> {
+    if data.len() < ETHERNET_HEADER_SIZE {
+        return Err(NetError::InvalidArgument);
+    }
+    if data.len() > ETHERNET_HEADER_SIZE + MTU {
+        return Err(NetError::MessageTooLong);
+    }
+
+    let packet = PacketBuffer::from_payload(&data);
+
+    let mut device = NETWORK_DEVICE.lock();
+    match *device {
+        Some(ref mut x) => x.send(packet)?,
+        None => return Err(NetError::NetworkDown),
+    }
+    Ok(data.len() as u32)
+}
+
+/// Encapsulate an IPv4 packet in an ethernet frame and transmit it.
+///
+/// Must not be called with the `SOCKETS` lock held.
+fn send_frame(
+    mut packet: PacketBuffer,
+    dest_hardware_address: EthernetAddress,
+) -> Result<(), NetError> {
+    let mut device = NETWORK_DEVICE.lock();
+    let device: &mut Box<dyn NetworkDevice> = match *device {
+        Some(ref mut x) => x,
+        None => return Err(NetError::NetworkDown),
+    };
+
+    let ethernet_frame = EthernetFrame::new(
+        dest_hardware_address,
+        device.hardware_address(),
+        Ethertype::IPV4,
+    );
+    packet.serialize(&ethernet_frame);
+    device.send(packet)
+}
+
+/// Receive a datagram from a socket.
+///
+/// Blocks, sleeping on the socket, until a datagram is available, the socket
+/// is shut down or the SO_RCVTIMEO timeout of the socket passes, failing with
+/// `NetError::TimedOut` in the latter case. A pending error on the socket, such
+/// as `NetError::ConnectionRefused`, is reported and cleared before any data
+/// is received. Each call
+/// returns at most one datagram, discarding any part of it that does not fit in
+/// `data`, along with the sender of the datagram. The behaviour can be adjusted
+/// with `flags`:
+///
+/// 	- MSG_PEEK: Copy the datagram without removing it from the socket.
+/// 	- MSG_DONTWAIT: Fail rather than block if no datagram is available. Implied
+///    for sockets created with SOCK_NONBLOCK.
+/// 	- MSG_TRUNC: Return the full length of the datagram, even if only part of
+///    it fit in `data`.
+/// 	- MSG_WAITALL: Keep receiving datagrams into `data` until it is full.
+///    Ignored with MSG_PEEK.
+///
+/// Unix domain stream sockets have no datagram boundaries, each call returns
+/// as much queued data as fits in `data`, leaving the rest queued, and returns
+/// end-of-file once the remote has hung up and the queue is empty. Data is
+/// scattered across the buffers of `data` in order, as if they were one.
+fn recv(socket_id: u32, data: &mut [&mut [u8]], flags: u32) -> Result<Received, NetError> {
+    if flags & !(MSG_PEEK | MSG_TRUNC | MSG_DONTWAIT | MSG_WAITALL) != 0 {
+        return Err(NetError::InvalidArgument);
+    }
+
+    let len = data.iter().map(|x| x.len()).sum::<usize>();
+    let mut copied = 0;
+    let mut received = Received {
+        len: 0,
+        peer: None,
+        timestamp: 0,
+        info: None,
+        truncated: false,
+    };
+    let mut deadline = None;
+    let mut sockets = SOCKETS.lock();
+    loop {
+        let socket = match sockets.get_mut(socket_id as usize) {
+            Some(x) => x,
+            None => return Err(NetError::BadSocket),
+        };
+
+        // Report end-of-file once the socket has been shut down.
+        if socket.shutdown {
+            return Ok(received);
+        }
+
+        if copied == 0 {
+            if let Some(x) = socket.error.take() {
+                return Err(x);
+            }
+        }
+
+        let stream = socket.r#type == SocketType::UnixStream;
+        if stream && socket.remote.is_none() && !socket.hangup {
+            return Err(NetError::NotConnected);
+        }
+
+        // Copy the most data we can from the next datagram to the userspace
+        // buffer.
+        if let Some(datagram) = socket.queue.front_mut() {
+            let datagram_len = datagram.data.len();
+            if copied == 0 {
+                received.peer = datagram.peer;
+                received.timestamp = datagram.timestamp;
+                received.info = datagram.info;
+            }
+
+            let copy_size = if datagram_len > len - copied {
+                len - copied
+            } else {
+                datagram_len
+            };
+            datagram
+                .data
+                .gather()
+                .range(0, copy_size)
+                .scatter(data, copied);
+            copied += copy_size;
+
+            received.len = if flags & MSG_TRUNC != 0 {
+                copied - copy_size + datagram_len
+            } else {
+                copied
+            } as u32;
+            received.truncated = copy_size < datagram_len && !stream;
+
+            if flags & MSG_PEEK != 0 {
+                return Ok(received);
+            }
+
+            if stream {
+                // Leave any data that did not fit queued for the next call.
+                if copy_size < datagram_len {
+                    datagram.data.consume(copy_size);
+                    socket.queued -= copy_size;
+                } else {
+                    socket.dequeue();
+                }
+
+                // Wake any writers waiting for room.
+                unsafe {
+                    wakeup(socket.channel());
+                    pollwakeup();
+                }
+                let drained = socket.queue.is_empty();
+                notify_writers(&sockets, socket_id as usize);
+
+                if copied == len || (flags & MSG_WAITALL == 0 && drained) {
+                    return Ok(received);
+                }
+                continue;
+            }
+
+            socket.dequeue();
+            if socket.is_unix() {
+                unsafe {
+                    wakeup(socket.channel());
+                    pollwakeup();
+                }
+                notify_writers(&sockets, socket_id as usize);
+            }
+
+            if flags & MSG_WAITALL == 0 || copy_size < datagram_len || copied == len {
+                return Ok(received);
+            }
+            continue;
+        }
+
+        // Report end-of-file once the remote has hung up.
+        if socket.hangup {
+            return Ok(received);
+        }
+
+        let timeout = match socket.rcvtimeo {
+            0 => None,
+            x => Some(*deadline.get_or_insert(now().wrapping_add(x))),
+        };
+
+        if copied > 0 && (flags & MSG_DONTWAIT != 0 || socket.nonblocking) {
+            return Ok(received);
+        }
+        if flags & MSG_DONTWAIT != 0 || socket.nonblocking {
+            return Err(NetError::WouldBlock);
+        }
+        if timeout.map_or(false, expired) {
+            return if copied > 0 {
+                Ok(received)
+            } else {
+                Err(NetError::TimedOut)
+            };
+        }
+
+        if unsafe { killed() } != 0 {
+            return Err(NetError::Interrupted);
+        }
+        let chan = socket.channel();
+        if let Some(x) = timeout {
+            wake_at(x, chan);
+        }
+        sockets = sockets.sleep(chan);
+    }
 }
 
-/// The bind system call.
-#[no_mangle]
-unsafe extern "C" fn sys_bind() -> i32 {
-    let mut socket_id: i32 = 0;
-    argint(0, &mut socket_id);
+/// Disable further sends and receives on a socket.
+fn shutdown_socket(socket_id: u32) -> Result<(), NetError> {
+    let mut sockets = SOCKETS.lock();
+    let socket = match sockets.get_mut(socket_id as usize) {
+        Some(x) => x,
+        None => return Err(NetError::BadSocket),
+    };
 
-    let mut source_address: i32 = 0;
-    argint(1, &mut source_address);
+    socket.shutdown = true;
+    socket.queue.clear();
+    socket.queued = 0;
 
-    let mut source_port: i32 = 0;
-    argint(2, &mut source_port);
+    // Wake any readers blocked on the socket.
+    unsafe {
+        wakeup(socket.channel());
+        pollwakeup();
+    }
+    socket.notify(socket_id as usize);
 
-    let source_port: u16 = match source_port.try_into() {
-        Ok(x) => x,
-        Err(_) => return 1,
-    };
+    let remote = socket.remote;
+    hang_up(&mut sockets, socket_id as usize, remote);
+    Ok(())
+}
 
-    match bind(socket_id as u32, source_address as u32, source_port) {
-        Ok(()) => 0,
-        Err(()) => 1,
+/// Clean up a socket and its resouces.
+fn close_socket(socket_id: u32) -> Result<(), NetError> {
+    let inode = remove_socket(&mut SOCKETS.lock(), socket_id as usize)?;
+    if let Some(x) = inode {
+        unsafe { sockiput(x as *mut c_void) };
     }
+    Ok(())
 }
 
-/// The connect system call.
-#[no_mangle]
-unsafe extern "C" fn sys_connect() -> i32 {
-    let mut socket_id: i32 = 0;
-    argint(0, &mut socket_id);
-
-    let mut dest_address: i32 = 0;
-    argint(1, &mut dest_address);
-
-    let mut dest_port: i32 = 0;
-    argint(2, &mut dest_port);
-
-    if socket_id < 0 {
-        return 1;
+/// Remove a socket from the table and release its resources.
+///
+/// Returns the socket inode held by the socket, if any, which must be released
+/// with sockiput(...) once the `SOCKETS` lock is dropped.
+fn remove_socket(
+    sockets: &mut HandleTable<Box<Socket>>,
+    socket_id: usize,
+) -> Result<Option<usize>, NetError> {
+    let socket = sockets.remove(socket_id).ok_or(NetError::BadSocket)?;
+    socket.release(socket_id);
+    hang_up(sockets, socket_id, socket.remote);
+
+    // Connections that were never accepted go with the listener.
+    for x in socket.backlog.iter() {
+        let _ = remove_socket(sockets, *x as usize);
     }
+    Ok(socket.inode)
+}
 
-    match connect(socket_id as u32, dest_address as u32, dest_port as u32) {
-        Ok(()) => 0,
-        Err(()) => 1,
+/// Tell the remote of a unix domain stream socket that no more data will be
+/// sent to it.
+fn hang_up(sockets: &mut HandleTable<Box<Socket>>, socket_id: usize, remote: Option<u32>) {
+    let remote_id = match remote {
+        Some(x) => x as usize,
+        None => return,
+    };
+    let remote = match sockets.get_mut(remote_id) {
+        Some(x) if x.r#type == SocketType::UnixStream && x.remote == Some(socket_id as u32) => x,
+        _ => return,
+    };
+    remote.hangup = true;
+    unsafe {
+        wakeup(remote.channel());
+        pollwakeup();
     }
+    remote.notify(remote_id);
 }
 
-/// The listen system call.
-#[no_mangle]
-unsafe extern "C" fn sys_listen() {}
-
-/// The accept system call.
-#[no_mangle]
-unsafe extern "C" fn sys_accept() {}
-
-/// The send system call.
-#[no_mangle]
-unsafe extern "C" fn sys_send() -> i32 {
-    let mut socket_id: i32 = 0;
-    argint(0, &mut socket_id);
-
-    let mut data: *mut u8 = core::ptr::null_mut();
-    let data_ptr: *const *mut u8 = &mut data;
-    argptr(1, data_ptr as _, 4);
-
-    let mut len: i32 = 0;
-    argint(2, &mut len);
-    let len = len as usize;
-
-    let data = unsafe { slice::from_raw_parts(data, len) };
-
-    match send(socket_id as u32, &data) {
-        Ok(n) => match i32::try_from(n) {
-            Ok(n) => n,
-            Err(_) => {
-                // i32::max() is much (much) larger than any current hardware supported frame
-                // size, so this should never happen under normal operation.
-                panic!("send\n\x00")
-            }
-        },
-        Err(_) => -1,
+/// Tell the epoll instances watching the unix domain sockets that send to
+/// `socket_id` that it may have room for their data.
+fn notify_writers(sockets: &HandleTable<Box<Socket>>, socket_id: usize) {
+    for (writer_id, writer) in sockets.iter() {
+        if writer.remote == Some(socket_id as u32) {
+            writer.notify(writer_id);
+        }
     }
 }
 
-/// The recv system call.
-#[no_mangle]
-unsafe extern "C" fn sys_recv() -> i32 {
-    let mut socket_id: i32 = 0;
-    argint(0, &mut socket_id);
+/// Register a socket with an epoll instance, change its events or unregister
+/// it, as requested by `op`.
+///
+/// Fails with `NetError::AlreadyExists` when adding a socket that is already
+/// registered, and `NetError::NotFound` when changing or removing one that is
+/// not.
+fn epoll_control(
+    epoll_id: usize,
+    op: i32,
+    socket_id: usize,
+    events: u32,
+    data: u32,
+) -> Result<(), NetError> {
+    let mut sockets = SOCKETS.lock();
+    let socket = sockets.get_mut(socket_id).ok_or(NetError::BadSocket)?;
+    let mut epolls = EPOLLS.lock();
+    let epoll = epolls.get_mut(epoll_id).ok_or(NetError::BadSocket)?;
+
+    match op {
+        EPOLL_CTL_ADD => {
+            epoll.add(socket_id as u32, events, data)?;
+            socket.watchers.push(epoll_id as u32);
+        }
+        EPOLL_CTL_MOD => epoll.modify(socket_id as u32, events, data)?,
+        EPOLL_CTL_DEL => {
+            epoll.remove(socket_id as u32)?;
+            socket.watchers.retain(|x| *x != epoll_id as u32);
+        }
+        _ => return Err(NetError::InvalidArgument),
+    }
 
-    let mut data: *mut u8 = core::ptr::null_mut();
-    let data_ptr: *const *mut u8 = &mut data;
-    argptr(1, data_ptr as _, 4);
+    // Added and changed sockets are checked for events straight away.
+    unsafe { wakeup(epoll.channel()) };
+    Ok(())
+}
 
-    let mut len: i32 = 0;
-    argint(2, &mut len);
-    let len = len as usize;
+/// Wait for up to `max` sockets registered with an epoll instance to have
+/// events to report, returning the events and data of each.
+///
+/// Only the sockets on the ready list of the instance are checked, under a
+/// single hold of the `SOCKETS` lock. Sleeps until a socket is pushed to the
+/// ready list, the timeout in ticks passes or the process is killed. A
+/// negative timeout waits forever, a timeout of zero never sleeps.
+fn epoll_wait(epoll_id: usize, max: usize, timeout: i32) -> Result<Vec<(u32, u32)>, NetError> {
+    let deadline = match timeout {
+        x if x > 0 => Some(now().wrapping_add(x as u32)),
+        _ => None,
+    };
 
-    let mut data = slice::from_raw_parts_mut(data, len);
+    loop {
+        let sockets = SOCKETS.lock();
+        let mut epolls = EPOLLS.lock();
+        let epoll = epolls.get_mut(epoll_id).ok_or(NetError::BadSocket)?;
+        let events = epoll.collect(max, |x| socket_events(&sockets, x as usize) as u32);
+        if !events.is_empty() || timeout == 0 || deadline.map_or(false, expired) {
+            return Ok(events);
+        }
+        if unsafe { killed() } != 0 {
+            return Err(NetError::Interrupted);
+        }
 
-    match recv(socket_id as u32, &mut data, len as u32) {
-        Ok(n) => n as i32,
-        Err(_) => return -1,
+        // Sockets are pushed to the ready list with the `EPOLLS` lock held, so
+        // none are missed by dropping the `SOCKETS` lock first.
+        drop(sockets);
+        let chan = epoll.channel();
+        if let Some(x) = deadline {
+            wake_at(x, chan);
+        }
+        drop(epolls.sleep(chan));
     }
 }
 
-/// The shutdown system call.
-#[no_mangle]
-unsafe extern "C" fn sys_shutdown() -> i32 {
-    let mut socket_id: i32 = 0;
-    argint(0, &mut socket_id);
+/// Bind a unix domain socket to a path, creating a socket inode there.
+///
+/// Fails with `NetError::AddressInUse` if the path already exists, even if no
+/// socket is bound to it, or `NetError::NotFound` if its directory does not.
+fn bind_unix(socket_id: u32, path: UnixPath) -> Result<(), NetError> {
+    // Check first, so a bound socket does not leave a stray inode behind.
+    match SOCKETS.lock().get(socket_id as usize) {
+        Some(x) if x.inode.is_none() => (),
+        Some(_) => return Err(NetError::InvalidArgument),
+        None => return Err(NetError::BadSocket),
+    }
 
-    match shutdown_socket(socket_id as u32) {
-        Ok(_) => 0,
-        Err(_) => 1,
+    let inode = unsafe { sockcreate(path.as_ptr()) };
+    if inode.is_null() {
+        let existing = unsafe { socklookup(path.as_ptr()) };
+        if existing.is_null() {
+            return Err(NetError::NotFound);
+        }
+        unsafe { sockiput(existing) };
+        return Err(NetError::AddressInUse);
     }
-}
 
-/// Create a new socket of the specified domain and return the socket identifer.
-fn create_socket(domain: SocketType) -> u32 {
     let mut sockets = SOCKETS.lock();
-    let socket_id = sockets.len();
-    let mut buffer = vec::Vec::<u8>::new();
-    buffer.reserve(BUFFER_SIZE);
-    sockets.insert(
-        socket_id,
-        Socket {
-            r#type: domain,
-            source_port: None,
-            source_address: None,
-            dest_port: None,
-            dest_protocol_address: None,
-            dest_hardware_address: None,
-            buffer: buffer,
-        },
-    );
-    socket_id as u32
+    let error = match sockets.get_mut(socket_id as usize) {
+        Some(x) if x.inode.is_none() => {
+            x.path = Some(path);
+            x.inode = Some(inode as usize);
+            return Ok(());
+        }
+        Some(_) => NetError::InvalidArgument,
+        None => NetError::BadSocket,
+    };
+    drop(sockets);
+    unsafe { sockiput(inode) };
+    Err(error)
 }
 
-/// Bind a socket to a local address and port.
+/// Find the unix domain socket of type `r#type` bound to a path.
 ///
-/// TODO:
-/// 	- Don't hardcode address to 10.0.0.2
-fn bind(socket_id: u32, _source_address: u32, source_port: u16) -> Result<(), ()> {
-    let mut sockets = SOCKETS.lock();
-    let mut socket = match sockets.get_mut(&(socket_id as usize)) {
-        Some(x) => x,
-        None => return Err(()),
-    };
-
-    socket.source_port = Some(source_port);
-    socket.source_address = Some(Ipv4Addr::from(0x0A000002 as u32));
+/// Fails with `NetError::NotFound` if the path does not exist, or
+/// `NetError::ConnectionRefused` if no such socket is bound to it. Must not be
+/// called with the `SOCKETS` lock held.
+fn find_unix(path: &UnixPath, r#type: SocketType) -> Result<u32, NetError> {
+    let inode = unsafe { socklookup(path.as_ptr()) };
+    if inode.is_null() {
+        return Err(NetError::NotFound);
+    }
 
-    Ok(())
+    let found = SOCKETS
+        .lock()
+        .iter_mut()
+        .find(|(_, x)| x.r#type == r#type && x.inode == Some(inode as usize))
+        .map(|(x, _)| x as u32);
+    unsafe { sockiput(inode) };
+    found.ok_or(NetError::ConnectionRefused)
 }
 
-/// Connect to a remote socket.
-fn connect(socket_id: u32, dest_address: u32, dest_port: u32) -> Result<(), ()> {
+/// Connect a unix domain socket to the socket bound to a path.
+///
+/// Datagram sockets send to the bound socket from then on. Stream sockets
+/// queue a new connection on the bound socket, which must be listening, for
+/// accept(...) to return; the connection is usable straight away. Fails with
+/// `NetError::ConnectionRefused` if the backlog of the listener is full.
+fn connect_unix(socket_id: u32, path: UnixPath) -> Result<(), NetError> {
+    let r#type = socket_type(socket_id)?;
+    let remote = find_unix(&path, r#type)?;
+
     let mut sockets = SOCKETS.lock();
-    let mut socket = match sockets.get_mut(&(socket_id as usize)) {
-        Some(x) => x,
-        None => return Err(()),
+    let socket = match sockets.get_mut(socket_id as usize) {
+        Some(x) if !x.shutdown => x,
+        Some(_) => return Err(NetError::Shutdown),
+        None => return Err(NetError::BadSocket),
     };
+    if r#type == SocketType::UnixDgram {
+        socket.remote = Some(remote);
+        return Ok(());
+    }
+    if socket.listening {
+        return Err(NetError::InvalidArgument);
+    }
+    if socket.remote.is_some() || socket.hangup {
+        return Err(NetError::IsConnected);
+    }
 
-    // Look up the desination hardware address from the cache or try and resolve it.
-    let dest_protocol_address = Ipv4Addr::from(dest_address as u32);
-    let dest_hardware_address = {
-        let arp_cache = ARP_CACHE.lock();
-        arp_cache.hardware_address(&dest_protocol_address)
+    let listener = match sockets.get(remote as usize) {
+        Some(x) if x.listening && x.backlog.len() < x.max_backlog => x,
+        _ => return Err(NetError::ConnectionRefused),
     };
-    let dest_hardware_address = match dest_hardware_address {
-        Some(x) => x,
-        None => {
-            // Address not in the cache. Make the request, release the device lock try and
-            // and block until the address is resolved.
-            {
-                let mut device = NETWORK_DEVICE.lock();
-                let device: &mut Box<dyn NetworkDevice> = match *device {
-                    Some(ref mut x) => x,
-                    None => return Err(()),
-                };
-                ArpCache::resolve(&dest_protocol_address, device);
-                drop(device);
-            }
-
-            // Wait 1 seconds for a response.
-            let timeout = rdtsc() + (CPU_FREQ_MHZ * 1_000_000);
-            loop {
-                if rdtsc() > timeout {
-                    break;
-                }
-            }
-
-            {
-                let arp_cache = ARP_CACHE.lock();
-                match arp_cache.hardware_address(&dest_protocol_address) {
-                    Some(x) => x,
-                    None => return Err(()),
-                }
-            }
+    let mut accepted = Socket::new(
+        SocketType::UnixStream,
+        listener.creator,
+        listener.owners.clone(),
+        false,
+    );
+    accepted.path = listener.path;
+    accepted.remote = Some(socket_id);
+    let accepted = sockets
+        .insert(Box::new(accepted))
+        .ok_or(NetError::SocketTableFull)?;
+
+    if let Some(x) = sockets.get_mut(socket_id as usize) {
+        x.remote = Some(accepted as u32);
+        x.notify(socket_id as usize);
+    }
+    if let Some(x) = sockets.get_mut(remote as usize) {
+        x.backlog.push_back(accepted as u32);
+        unsafe {
+            wakeup(x.channel());
+            pollwakeup();
         }
-    };
+        x.notify(remote as usize);
+    }
+    Ok(())
+}
 
-    // Populate the Socket with the address of the local adaptor, a new ephermal
-    // port and the details of the remote.
-    socket.source_port = Some((1024 + socket_id) as u16);
-    socket.source_address = Some(Ipv4Addr::from(0x0A000002 as u32));
-    socket.dest_port = Some((dest_port as i16).try_into().unwrap());
-    socket.dest_hardware_address = Some(dest_hardware_address);
-    socket.dest_protocol_address = Some(dest_protocol_address);
+/// Listen for connections on a bound unix domain stream socket, holding up
+/// to `backlog` connections, at most `MAX_BACKLOG`, until they are accepted.
+fn listen(socket_id: u32, backlog: i32) -> Result<(), NetError> {
+    let mut sockets = SOCKETS.lock();
+    let socket = match sockets.get_mut(socket_id as usize) {
+        Some(x) if x.r#type == SocketType::UnixStream => x,
+        Some(_) => return Err(NetError::OperationNotSupported),
+        None => return Err(NetError::BadSocket),
+    };
+    if socket.inode.is_none() || socket.remote.is_some() || socket.shutdown {
+        return Err(NetError::InvalidArgument);
+    }
 
+    socket.listening = true;
+    socket.max_backlog = (backlog.max(1) as usize).min(MAX_BACKLOG);
     Ok(())
 }
 
-/// Encapsulate and send data on a socket.
+/// Accept the next connection to a listening unix domain stream socket.
 ///
-/// TODO:
-/// 	- Refactor packet building to one place.
-/// 	- Check socket has been setup with connect(...).
-fn send(socket_id: u32, data: &[u8]) -> Result<u32, ()> {
+/// Blocks until a connection arrives, unless the socket is non-blocking, or
+/// the SO_RCVTIMEO timeout of the socket passes. Returns the identifier of the
+/// socket for the connection, now held by the holders of the listener, and
+/// the path of the connecting socket.
+fn accept(socket_id: u32) -> Result<(u32, Option<UnixPath>), NetError> {
+    let mut deadline = None;
     let mut sockets = SOCKETS.lock();
-    let socket = match sockets.get_mut(&(socket_id as usize)) {
-        Some(x) => x,
-        None => return Err(()),
-    };
+    loop {
+        let listener = match sockets.get_mut(socket_id as usize) {
+            Some(x) if x.listening => x,
+            Some(_) => return Err(NetError::InvalidArgument),
+            None => return Err(NetError::BadSocket),
+        };
+
+        if let Some(accepted) = listener.backlog.pop_front() {
+            let owners = listener.owners.clone();
+            let remote = match sockets.get_mut(accepted as usize) {
+                Some(x) => {
+                    x.owners = owners;
+                    x.remote
+                }
+                None => continue,
+            };
+            let path = remote
+                .and_then(|x| sockets.get(x as usize))
+                .and_then(|x| x.path);
+            return Ok((accepted, path));
+        }
 
-    // Create a new packet buffer.
-    let mut packet = PacketBuffer::new(BUFFER_SIZE);
+        let timeout = match listener.rcvtimeo {
+            0 => None,
+            x => Some(*deadline.get_or_insert(now().wrapping_add(x))),
+        };
+        if listener.nonblocking {
+            return Err(NetError::WouldBlock);
+        }
+        if timeout.map_or(false, expired) {
+            return Err(NetError::TimedOut);
+        }
+        if unsafe { killed() } != 0 {
+            return Err(NetError::Interrupted);
+        }
 
-    // Build and write the udp packet, sending up to a maximum of 1024 bytes.
-    let data_len: u16 = if data.len() > 1024 {
-        1024
-    } else {
-        data.len() as u16
-    };
+        let chan = listener.channel();
+        if let Some(x) = timeout {
+            wake_at(x, chan);
+        }
+        sockets = sockets.sleep(chan);
+    }
+}
 
-    let udp_packet = UdpPacket::new(
-        socket.source_port.unwrap(),
-        socket.dest_port.unwrap(),
-        data[..data_len as usize].to_vec(),
-    );
-    packet.serialize(&udp_packet);
+/// Send data from a unix domain socket to the socket `remote`.
+///
+/// The data is queued directly on the remote, no network device is involved.
+/// Sleeps while the remote has no room, unless the socket is non-blocking or
+/// MSG_DONTWAIT is set. Datagrams are queued whole, and must fit in SO_SNDBUF,
+/// the SO_RCVBUF of the remote and the 65507 bytes of the largest UDP
+/// datagram. Streams are queued a piece at a time as room becomes available,
+/// returning the number of bytes queued before the send would block or the
+/// remote hung up.
+fn send_unix(
+    socket_id: u32,
+    remote: u32,
+    data: Gather<&[u8]>,
+    flags: u32,
+) -> Result<u32, NetError> {
+    if flags & !MSG_DONTWAIT != 0 {
+        return Err(NetError::InvalidArgument);
+    }
 
-    // Build and write the IP packet.
-    let ip_packet = Ipv4Packet::new(
-        0,
-        0,
-        (packet.len() + 20) as u16,
-        0,
-        true,
-        false,
-        0,
-        64,
-        Protocol::UDP,
-        socket.source_address.unwrap(),
-        socket.dest_protocol_address.unwrap(),
-    );
-    packet.serialize(&ip_packet);
+    let mut sent = 0;
+    let mut sockets = SOCKETS.lock();
+    loop {
+        let (stream, nonblocking) = match sockets.get(socket_id as usize) {
+            Some(x) if x.shutdown => return Err(NetError::Shutdown),
+            Some(x) if x.r#type == SocketType::UnixStream && x.hangup => {
+                return if sent > 0 {
+                    Ok(sent as u32)
+                } else {
+                    Err(NetError::Shutdown)
+                };
+            }
+            Some(x)
+                if x.r#type == SocketType::UnixDgram
+                    && (data.len() > x.sndbuf as usize || data.len() > MAX_UDP_PAYLOAD) =>
+            {
+                return Err(NetError::MessageTooLong)
+            }
+            Some(x) => (
+                x.r#type == SocketType::UnixStream,
+                x.nonblocking || flags & MSG_DONTWAIT != 0,
+            ),
+            None => return Err(NetError::BadSocket),
+        };
+        if stream && data.is_empty() {
+            return Ok(0);
+        }
 
-    // Write the ethernet frame header and send the frame.
-    let mut device = NETWORK_DEVICE.lock();
-    let device: &mut Box<dyn NetworkDevice> = match *device {
-        Some(ref mut x) => x,
-        None => return Err(()),
-    };
+        let remote_id = remote as usize;
+        let remote = match sockets.get_mut(remote_id) {
+            Some(x) if !x.shutdown => x,
+            _ if stream => return Err(NetError::Shutdown),
+            _ => return Err(NetError::ConnectionRefused),
+        };
 
-    let ethernet_frame = EthernetFrame::new(
-        socket.dest_hardware_address.unwrap(),
-        device.hardware_address(),
-        Ethertype::IPV4,
-    );
-    packet.serialize(&ethernet_frame);
+        let room = remote.room();
+        if !stream && data.len() > remote.rcvbuf as usize {
+            return Err(NetError::MessageTooLong);
+        }
+        if (stream && room > 0) || (!stream && room >= data.len()) {
+            let (size, peer) = if stream {
+                ((data.len() - sent).min(room), None)
+            } else {
+                (data.len(), Some(Sender::Unix(socket_id)))
+            };
+            let datagram = Datagram {
+                peer: peer,
+                timestamp: now(),
+                info: None,
+                data: Chunks::from_gather(data.range(sent, sent + size)),
+            };
+            let _ = remote.enqueue(datagram);
+            sent += size;
+            unsafe {
+                wakeup(remote.channel());
+                pollwakeup();
+            }
+            remote.notify(remote_id);
 
-    device.send(packet);
+            if sent == data.len() {
+                return Ok(sent as u32);
+            }
+            continue;
+        }
 
-    // Encapsulate the data in a UDP packet.
-    Ok(data_len as u32)
+        if nonblocking || unsafe { killed() } != 0 {
+            return match (sent, nonblocking) {
+                (0, true) => Err(NetError::WouldBlock),
+                (0, false) => Err(NetError::Interrupted),
+                _ => Ok(sent as u32),
+            };
+        }
+        let chan = remote.channel();
+        sockets = sockets.sleep(chan);
+    }
 }
 
-/// Read available data from a socket.
+/// Send `count` bytes of `file`, starting at `offset`, on a connected socket.
 ///
-/// This call is non-blocking, returning immediately if no data is
-/// available.
-fn recv(socket_id: u32, data: &mut [u8], len: u32) -> Result<u32, ()> {
-    let mut sockets = SOCKETS.lock();
-    let socket = match sockets.get_mut(&(socket_id as usize)) {
-        Some(x) => x,
-        None => return Err(()),
+/// On UDP sockets the file is read through the buffer cache straight into the
+/// payload of each outgoing packet, each filling a datagram of one frame. On
+/// unix domain sockets it is read into a kernel buffer and queued up to a page
+/// at a time with send(...). Stops early at the end of the file, or if the
+/// process is killed. Returns the number of bytes sent, or the error if none
+/// were.
+fn send_file(
+    socket_id: u32,
+    file: *mut c_void,
+    offset: u32,
+    count: usize,
+) -> Result<u32, NetError> {
+    let (r#type, sndbuf) = {
+        let sockets = SOCKETS.lock();
+        let socket = sockets.get(socket_id as usize).ok_or(NetError::BadSocket)?;
+        (socket.r#type, socket.sndbuf as usize)
     };
 
-    // Does the socket have any data available?
-    if socket.buffer.len() == 0 {
-        return Ok(0);
+    match r#type {
+        // Leave room for the IP and UDP headers.
+        SocketType::UDP => {
+            send_file_udp(socket_id, file, offset, count, (MTU - 20 - 8).min(sndbuf))
+        }
+        SocketType::UnixDgram => {
+            send_file_unix(socket_id, file, offset, count, PAGE_SIZE.min(sndbuf))
+        }
+        SocketType::UnixStream => send_file_unix(socket_id, file, offset, count, PAGE_SIZE),
+        _ => Err(NetError::InvalidArgument),
     }
+}
 
-    // Copy the most data we can from the socket buffer to userspace buffer.
-    let len = len as usize;
-    let copy_size = if socket.buffer.len() > len {
-        len
-    } else {
-        socket.buffer.len()
+/// Send a file on a connected UDP socket in datagrams of at most `size` bytes,
+/// as send_file(...) does.
+///
+/// While the transmit ring of the device is full the process sleeps and sends
+/// the datagram again, unless the socket is non-blocking.
+fn send_file_udp(
+    socket_id: u32,
+    file: *mut c_void,
+    offset: u32,
+    count: usize,
+    size: usize,
+) -> Result<u32, NetError> {
+    let (outgoing, dest, dest_hardware_address, nonblocking) = {
+        let mut sockets = SOCKETS.lock();
+        let socket = match sockets.get_mut(socket_id as usize) {
+            Some(x) if !x.shutdown => x,
+            Some(_) => return Err(NetError::Shutdown),
+            None => return Err(NetError::BadSocket),
+        };
+        if let Some(x) = socket.error.take() {
+            return Err(x);
+        }
+        let dest = match (socket.dest_protocol_address, socket.dest_port) {
+            (Some(a), Some(p)) => Peer {
+                address: a,
+                port: p,
+            },
+            _ => return Err(NetError::NotConnected),
+        };
+        let dest_hardware_address = socket.dest_hardware_address.ok_or(NetError::NotConnected)?;
+        (
+            socket.outgoing(),
+            dest,
+            dest_hardware_address,
+            socket.nonblocking,
+        )
     };
-    data[..copy_size].copy_from_slice(&socket.buffer[..copy_size]);
 
-    // Shrink the socket buffer to remove the copied data.
-    let new_size = socket.buffer.len() - copy_size;
-    socket.buffer.truncate(new_size);
+    let mut sent = 0;
+    while sent < count {
+        let len = size.min(count - sent);
+        let mut packet = PacketBuffer::with_payload(len);
+        let n = unsafe {
+            filereadat(
+                file,
+                packet.payload_mut().as_mut_ptr(),
+                offset.wrapping_add(sent as u32),
+                len as i32,
+            )
+        };
+        let result = match n {
+            0 => break,
+            x if x < 0 => Err(NetError::InvalidArgument),
+            x => {
+                packet.truncate_payload(x as usize);
+                let header = UdpPacket::header(outgoing.source.port, dest.port, x as usize);
+                packet.serialize(&header[..]);
+                send_ip_buffer(
+                    &outgoing,
+                    Protocol::UDP,
+                    dest.address,
+                    dest_hardware_address,
+                    packet,
+                )
+                .map(|_| x as usize)
+            }
+        };
+        let result = match result {
+            // The same bytes are read and sent again once the ring drains.
+            Err(NetError::NoBuffers) if !nonblocking => wait_transmit(socket_id).map(|_| 0),
+            x => x,
+        };
+        match result {
+            Ok(x) => sent += x,
+            Err(x) if sent == 0 => return Err(x),
+            Err(_) => break,
+        }
 
-    Ok(copy_size.try_into().unwrap())
+        if unsafe { killed() } != 0 {
+            break;
+        }
+    }
+    Ok(sent as u32)
 }
 
-/// Clean up a socket and its resouces.
-fn shutdown_socket(socket_id: u32) -> Result<(), ()> {
-    let mut sockets = SOCKETS.lock();
-    match sockets.remove(&(socket_id as usize)) {
-        Some(_) => Ok(()),
-        None => Err(()),
+/// Send a file on a connected unix domain socket `size` bytes at a time, as
+/// send_file(...) does.
+fn send_file_unix(
+    socket_id: u32,
+    file: *mut c_void,
+    offset: u32,
+    count: usize,
+    size: usize,
+) -> Result<u32, NetError> {
+    let mut buf = vec![0; size];
+    let mut sent = 0;
+    while sent < count {
+        let n = unsafe {
+            filereadat(
+                file,
+                buf.as_mut_ptr(),
+                offset.wrapping_add(sent as u32),
+                size.min(count - sent) as i32,
+            )
+        };
+        let result = match n {
+            0 => break,
+            x if x < 0 => Err(NetError::InvalidArgument),
+            x => send(socket_id, Gather::new(&[&buf[..x as usize]]), 0),
+        };
+        match result {
+            Ok(x) => sent += x as usize,
+            Err(x) if sent == 0 => return Err(x),
+            Err(_) => break,
+        }
+
+        if unsafe { killed() } != 0 {
+            break;
+        }
+    }
+    Ok(sent as u32)
+}
+
+/// Wait for the transmit ring of the network device to drain.
+///
+/// Nothing signals space in the ring, so the process sleeps on the socket for
+/// a tick. Fails with `NetError::Interrupted` if the process is killed.
+fn wait_transmit(socket_id: u32) -> Result<(), NetError> {
+    if unsafe { killed() } != 0 {
+        return Err(NetError::Interrupted);
     }
+
+    let sockets = SOCKETS.lock();
+    let socket = sockets.get(socket_id as usize).ok_or(NetError::BadSocket)?;
+    let chan = socket.channel();
+    wake_at(now().wrapping_add(1), chan);
+    drop(sockets.sleep(chan));
+    Ok(())
 }
 
 /// Main entrypoint for network device interrupts.
@@ -466,6 +3583,9 @@ fn handle_interrupt() {
 /// Handles a single, ethernet frame encapsulated packet. Potentially writes
 /// packets back to the network device.
 fn handle_packet(mut buffer: PacketBuffer, device: &mut Box<dyn NetworkDevice>) {
+    // Packet sockets see every frame before the stack handles it.
+    deliver_frame(buffer.remaining());
+
     let ethernet_frame = match buffer.parse::<EthernetFrame>() {
         Ok(x) => x,
         Err(_) => return,
@@ -473,9 +3593,14 @@ fn handle_packet(mut buffer: PacketBuffer, device: &mut Box<dyn NetworkDevice>)
 
     match ethernet_frame.ethertype {
         Ethertype::IPV4 => {
+            // Raw sockets see the whole packet, including the header, before
+            // the stack handles it.
+            deliver_raw(buffer.remaining());
+
+            // Fragments are not reassembled, so only raw sockets see them.
             let ip_packet = match buffer.parse::<Ipv4Packet>() {
-                Ok(x) => x,
-                Err(_) => return,
+                Ok(x) if !x.is_fragment() => x,
+                _ => return,
             };
 
             match ip_packet.protocol() {
@@ -502,15 +3627,16 @@ fn handle_packet(mut buffer: PacketBuffer, device: &mut Box<dyn NetworkDevice>)
                             Ethertype::IPV4,
                         );
                         x.serialize(&ethernet_frame);
-                        device.send(x);
+                        let _ = device.send(x);
                     }
                     None => (),
                 },
+                Protocol::IGMP => handle_igmp(&ip_packet, &mut buffer),
                 Protocol::UDP => {
-                    handle_udp(&mut buffer);
+                    handle_udp(&ip_packet, &mut buffer);
                 }
                 Protocol::TCP => (),
-                Protocol::UNKNOWN => (),
+                Protocol::Other(_) => (),
             }
         }
         Ethertype::ARP => match handle_arp(&mut buffer, &device) {
@@ -522,7 +3648,7 @@ fn handle_packet(mut buffer: PacketBuffer, device: &mut Box<dyn NetworkDevice>)
                     Ethertype::ARP,
                 );
                 x.serialize(&ethernet_frame);
-                device.send(x);
+                let _ = device.send(x);
             }
             None => (),
         },
@@ -534,7 +3660,101 @@ fn handle_packet(mut buffer: PacketBuffer, device: &mut Box<dyn NetworkDevice>)
     }
 }
 
+/// Queue a copy of an inbound ethernet frame on each packet socket for its
+/// Ethertype.
+fn deliver_frame(data: &[u8]) {
+    if data.len() < ETHERNET_HEADER_SIZE {
+        return;
+    }
+    let ethertype = u16::from_be_bytes([data[12], data[13]]);
+
+    let mut sockets = SOCKETS.lock();
+    let socket_ids = DEMUX.lock().lookup_frame(ethertype);
+
+    let mut woken = false;
+    for socket_id in socket_ids {
+        let socket = match sockets.get_mut(socket_id) {
+            Some(x) if !x.shutdown => x,
+            _ => continue,
+        };
+
+        let datagram = Datagram {
+            peer: None,
+            timestamp: now(),
+            info: None,
+            data: Chunks::from(data),
+        };
+        if socket.enqueue(datagram).is_ok() {
+            unsafe { wakeup(socket.channel()) };
+            socket.notify(socket_id);
+            woken = true;
+        }
+    }
+
+    if woken {
+        unsafe { pollwakeup() };
+    }
+}
+
+/// Queue a copy of an inbound IPv4 packet, header included, on each raw
+/// socket for its protocol.
+///
+/// Raw sockets bound to an address only see packets sent to it, and connected
+/// raw sockets only see packets from their peer.
+fn deliver_raw(data: &[u8]) {
+    let ip_packet = match Ipv4Packet::from_slice(data) {
+        Ok(x) => x,
+        Err(_) => return,
+    };
+    // Drop any padding added to fill out a short ethernet frame.
+    let data = &data[..(ip_packet.total_length() as usize).min(data.len())];
+
+    let mut sockets = SOCKETS.lock();
+    let socket_ids = DEMUX.lock().lookup_raw(ip_packet.protocol());
+
+    let mut woken = false;
+    for socket_id in socket_ids {
+        let socket = match sockets.get_mut(socket_id) {
+            Some(x) if !x.shutdown => x,
+            _ => continue,
+        };
+        match socket.source_address {
+            Some(x) if !x.is_unspecified() && x != ip_packet.destination() => continue,
+            _ => (),
+        }
+        match socket.dest_protocol_address {
+            Some(x) if x != ip_packet.source() => continue,
+            _ => (),
+        }
+
+        let datagram = Datagram {
+            peer: Some(Sender::Inet(Peer {
+                address: ip_packet.source(),
+                port: 0,
+            })),
+            timestamp: now(),
+            info: Some(PacketInfo {
+                destination: ip_packet.destination(),
+                time_to_live: ip_packet.time_to_live(),
+            }),
+            data: Chunks::from(data),
+        };
+        if socket.enqueue(datagram).is_ok() {
+            unsafe { wakeup(socket.channel()) };
+            socket.notify(socket_id);
+            woken = true;
+        }
+    }
+
+    if woken {
+        unsafe { pollwakeup() };
+    }
+}
+
 /// Handle an ICMP packet.
+///
+/// Replies to echo requests, and reports port unreachable messages to the
+/// socket that sent the undeliverable datagram.
 pub fn handle_icmp(buffer: &mut PacketBuffer) -> Option<PacketBuffer> {
     let icmp_packet = match buffer.parse::<IcmpPacket>() {
         Ok(x) => x,
@@ -545,15 +3765,62 @@ pub fn handle_icmp(buffer: &mut PacketBuffer) -> Option<PacketBuffer> {
         IcmpPacket::EchoMessage(x) => {
             if x.r#type == Type::EchoRequest {
                 let reply = IcmpPacket::EchoMessage(IcmpEchoMessage::from_request(x));
-                let mut packet = PacketBuffer::new(BUFFER_SIZE);
-                packet.serialize(&reply);
-                return Some(packet);
+                return Some(PacketBuffer::from_payload(&reply));
+            }
+        }
+        IcmpPacket::UnreachableMessage(x) => {
+            if x.code == PORT_UNREACHABLE {
+                refused(&x.data);
             }
         }
     }
     None
 }
 
+/// Report a port unreachable message to the connected socket that sent the
+/// datagram it quotes, failing its next send or receive with
+/// `NetError::ConnectionRefused`.
+fn refused(quoted: &[u8]) {
+    // The message quotes the IP header and at least the UDP ports of the
+    // datagram.
+    let ip_packet = match Ipv4Packet::from_slice(quoted) {
+        Ok(x) if x.protocol() == Protocol::UDP && quoted.len() >= x.header_size() + 4 => x,
+        _ => return,
+    };
+    let ports = &quoted[ip_packet.header_size()..];
+    let source_port = u16::from_be_bytes([ports[0], ports[1]]);
+    let dest_port = u16::from_be_bytes([ports[2], ports[3]]);
+
+    let mut sockets = SOCKETS.lock();
+    let socket_id = match DEMUX.lock().lookup(
+        Protocol::UDP,
+        ip_packet.source(),
+        source_port,
+        ip_packet.destination(),
+        dest_port,
+    ) {
+        Some(x) => x,
+        None => return,
+    };
+
+    // Only connected sockets hear about errors, as with other systems.
+    let socket = match sockets.get_mut(socket_id) {
+        Some(x)
+            if x.dest_protocol_address == Some(ip_packet.destination())
+                && x.dest_port == Some(dest_port) =>
+        {
+            x
+        }
+        _ => return,
+    };
+    socket.error = Some(NetError::ConnectionRefused);
+    unsafe {
+        wakeup(socket.channel());
+        pollwakeup();
+    }
+    socket.notify(socket_id);
+}
+
 /// Handle an ARP packet.
 ///
 /// Handle an ARP packet, optionally returning any response that needs to be
@@ -575,55 +3842,135 @@ pub fn handle_arp(
                 // Build the ARP reply.
                 let hardware_address = device.hardware_address();
                 let reply = ArpPacket::from_request(&arp_packet, hardware_address);
-                let mut packet = PacketBuffer::new(BUFFER_SIZE);
-                packet.serialize(&reply);
-                return Some(packet);
+                return Some(PacketBuffer::from_payload(&reply));
             }
         }
         arp::Operation::Reply => {
-            let mut arp_cache = ARP_CACHE.lock();
-            arp_cache.reply(arp_packet);
+            let (protocol_address, hardware_address) = (arp_packet.spa, arp_packet.sha);
+            ARP_CACHE.lock().reply(arp_packet);
+
+            // Wake anything waiting on the address.
+            resolved(protocol_address, Some(hardware_address));
+            unsafe { wakeup(arp_channel()) };
         }
         arp::Operation::Unknown => (),
     }
     None
 }
 
+/// Handle an IGMP message.
+///
+/// Queries schedule membership reports for the groups they ask about, which
+/// update_groups(...) sends once due. A report from another host for a group
+/// suppresses the report due from this host, one per network is enough.
+fn handle_igmp(ip_packet: &Ipv4Packet, buffer: &mut PacketBuffer) {
+    // The checksum covers the whole message, but not the padding of a short
+    // ethernet frame.
+    let data = buffer.remaining();
+    let len = (ip_packet.total_length() as usize).saturating_sub(ip_packet.header_size());
+    let packet = match IgmpPacket::from_buffer(&data[..len.min(data.len())]) {
+        Ok(x) => x,
+        Err(_) => return,
+    };
+
+    let mut groups = GROUPS.lock();
+    match packet.r#type {
+        igmp::Type::MembershipQuery => groups.query(packet.group, packet.max_response_time),
+        igmp::Type::V1MembershipReport | igmp::Type::V2MembershipReport => {
+            groups.report_heard(packet.group)
+        }
+        _ => (),
+    }
+}
+
 /// Handle a UDP packet.
 ///
 /// If this packet is destined for a socket and that socket has space in its
-/// buffer, copy the packet data into the socket buffer.
-pub fn handle_udp(buffer: &mut PacketBuffer) {
+/// queue, queue the packet data as a new datagram.
+pub fn handle_udp(ip_packet: &Ipv4Packet, buffer: &mut PacketBuffer) {
     let packet = match buffer.parse::<UdpPacket>() {
         Ok(x) => x,
         Err(_) => return,
     };
 
-    // Is this packet destined for an active socket?
+    // Only accept packets sent to the local adaptor, or broadcast or multicast
+    // on the local network.
+    let destination = ip_packet.destination();
+    let multicast = destination.is_multicast();
+    let broadcast = is_broadcast(destination) || multicast;
+    if !broadcast && destination != Ipv4Addr::from(LOCAL_ADDRESS) {
+        return;
+    }
+
+    // Is this packet destined for active sockets? Unicast packets go to the
+    // best matching socket, broadcasts to every socket bound to the wildcard
+    // address on the port, and multicasts to those of them in the group.
     let mut sockets = SOCKETS.lock();
-    let socket_id = {
-        let mut socket_id = None;
-        for (k, v) in sockets.iter() {
-            if Some(packet.dest_port()) == v.source_port {
-                socket_id = Some(k);
-                break;
-            }
+    let socket_ids = {
+        let demux = DEMUX.lock();
+        let (source, source_port) = (ip_packet.source(), packet.source_port());
+        if broadcast {
+            demux.lookup_all(
+                Protocol::UDP,
+                destination,
+                packet.dest_port(),
+                source,
+                source_port,
+            )
+        } else {
+            demux
+                .lookup(
+                    Protocol::UDP,
+                    destination,
+                    packet.dest_port(),
+                    source,
+                    source_port,
+                )
+                .into_iter()
+                .collect()
         }
+    };
 
-        match socket_id {
-            Some(id) => *id,
-            None => return,
+    let mut woken = false;
+    for socket_id in socket_ids {
+        let socket = match sockets.get_mut(socket_id) {
+            Some(x) if !x.shutdown => x,
+            Some(_) => continue,
+            // A stale endpoint of a removed socket, the datagram is dropped.
+            None => {
+                DEMUX
+                    .lock()
+                    .remove(Protocol::UDP, packet.dest_port(), socket_id);
+                continue;
+            }
+        };
+        if multicast && !socket.groups.contains(&destination) {
+            continue;
         }
-    };
 
-    let socket = match sockets.get_mut(&(socket_id as usize)) {
-        Some(x) => x,
-        None => panic!("socket not found\n\x00"),
-    };
+        // Queue the datagram if the socket has space for it.
+        let datagram = Datagram {
+            peer: Some(Sender::Inet(Peer {
+                address: ip_packet.source(),
+                port: packet.source_port(),
+            })),
+            timestamp: now(),
+            info: Some(PacketInfo {
+                destination: destination,
+                time_to_live: ip_packet.time_to_live(),
+            }),
+            data: Chunks::from(packet.data()),
+        };
+        if socket.enqueue(datagram).is_err() {
+            continue;
+        }
 
-    // Do we have space in the socket buffer for the new data?
-    if socket.buffer.len() + packet.data().len() >= BUFFER_SIZE {
-        return;
+        // Wake any readers blocked on the socket.
+        unsafe { wakeup(socket.channel()) };
+        socket.notify(socket_id);
+        woken = true;
+    }
+    if woken {
+        unsafe { pollwakeup() };
     }
-    socket.buffer.extend_from_slice(&packet.data());
 }
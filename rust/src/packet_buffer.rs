@@ -1,7 +1,11 @@
 use alloc::vec;
 use alloc::vec::Vec;
 
-pub static BUFFER_SIZE: usize = 2048;
+use crate::error::NetError;
+
+/// Room left in front of a payload for the headers that encapsulate it: an
+/// ethernet header, an IPv4 header without options and a UDP header.
+const HEADER_ROOM: usize = 14 + 20 + 8;
 
 /// Represents raw packet data.
 ///
@@ -28,6 +32,41 @@ impl PacketBuffer {
         }
     }
 
+    /// Create a new buffer holding `payload`, sized to fit it along with the
+    /// headers that encapsulate it.
+    pub fn from_payload<T: ToBuffer + ?Sized>(payload: &T) -> PacketBuffer {
+        let mut packet_buffer = PacketBuffer::new(payload.size() + HEADER_ROOM);
+        packet_buffer.serialize(payload);
+        packet_buffer
+    }
+
+    /// Create a new buffer with room for a payload of `size` bytes, written in
+    /// place with `payload_mut`, along with the headers that encapsulate it.
+    pub fn with_payload(size: usize) -> PacketBuffer {
+        let mut packet_buffer = PacketBuffer::new(size + HEADER_ROOM);
+        packet_buffer.offset = size;
+        packet_buffer.written = true;
+        packet_buffer
+    }
+
+    /// Return the payload of a buffer created with `with_payload`.
+    ///
+    /// Must be called before any header is serialized.
+    pub fn payload_mut(&mut self) -> &mut [u8] {
+        let start = self.buf.len() - self.offset;
+        &mut self.buf[start..]
+    }
+
+    /// Shorten the payload of a buffer created with `with_payload` to its
+    /// first `len` bytes.
+    ///
+    /// Must be called before any header is serialized.
+    pub fn truncate_payload(&mut self, len: usize) {
+        let excess = self.offset - len.min(self.offset);
+        self.buf.truncate(self.buf.len() - excess);
+        self.offset -= excess;
+    }
+
     /// Create a new buffer from the data provided.
     pub fn new_from_bytes(data: *const u8, size: usize) -> PacketBuffer {
         let mut packet_buffer = PacketBuffer {
@@ -44,18 +83,15 @@ impl PacketBuffer {
 
     /// Parse a new packet from the buffer.
     /// TODO: Zero-copy?
-    pub fn parse<T: FromBuffer>(&mut self) -> Result<T, ()> {
-        let value = match T::from_buffer(&self.buf[self.offset..]) {
-            Ok(x) => x,
-            Err(_) => return Err(()),
-        };
+    pub fn parse<T: FromBuffer>(&mut self) -> Result<T, NetError> {
+        let value = T::from_buffer(&self.buf[self.offset..])?;
         self.offset += value.size();
         Ok(value)
     }
 
     /// Serialize a new packet to the buffer.
     /// TODO: Zero-copy?
-    pub fn serialize<T: ToBuffer>(&mut self, value: &T) {
+    pub fn serialize<T: ToBuffer + ?Sized>(&mut self, value: &T) {
         self.offset += value.size();
         self.written = true;
         let start = self.buf.len() - self.offset;
@@ -63,6 +99,11 @@ impl PacketBuffer {
         value.to_buffer(&mut self.buf[start..end]);
     }
 
+    /// Return the bytes of a received packet that have not been parsed yet.
+    pub fn remaining(&self) -> &[u8] {
+        &self.buf[self.offset..self.size]
+    }
+
     /// Return the size of the buffer.
     pub fn len(&self) -> usize {
         self.offset
@@ -81,7 +122,10 @@ impl PacketBuffer {
 /// Represents a type that can be parsed from a PacketBuffer.
 pub trait FromBuffer {
     /// Parse a new instance from a slice of bytes.
-    fn from_buffer(buf: &[u8]) -> Result<Self, ()>
+    ///
+    /// Fails with `NetError::Malformed` if `buf` does not hold a valid
+    /// instance.
+    fn from_buffer(buf: &[u8]) -> Result<Self, NetError>
     where
         Self: Sized;
 
@@ -97,3 +141,14 @@ pub trait ToBuffer {
     /// The size of the serialized structure, including any encapsulated data.
     fn size(&self) -> usize;
 }
+
+/// Raw bytes, such as a payload built in user space, serialize as is.
+impl ToBuffer for [u8] {
+    fn to_buffer(&self, buf: &mut [u8]) {
+        buf.copy_from_slice(self);
+    }
+
+    fn size(&self) -> usize {
+        self.len()
+    }
+}
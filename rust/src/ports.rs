@@ -0,0 +1,116 @@
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+use crate::error::NetError;
+use crate::ip::{Ipv4Addr, Protocol};
+
+/// The first port handed out for implicit (ephemeral) binds.
+pub const EPHEMERAL_PORT_FIRST: u16 = 49152;
+
+/// The last port handed out for implicit (ephemeral) binds.
+pub const EPHEMERAL_PORT_LAST: u16 = 65535;
+
+/// A socket bound to a local port.
+#[derive(Debug, Copy, Clone)]
+pub struct Binding {
+    /// The socket holding the port.
+    pub socket: usize,
+    /// The local address the socket is bound to, unspecified for any address.
+    pub address: Ipv4Addr,
+    /// Set with SO_REUSEADDR.
+    pub reuse_address: bool,
+    /// Set with SO_REUSEPORT.
+    pub reuse_port: bool,
+}
+
+impl Binding {
+    /// Can the two bindings not hold the same port at the same time?
+    fn conflicts(&self, other: &Binding) -> bool {
+        if !self.address.is_unspecified()
+            && !other.address.is_unspecified()
+            && self.address != other.address
+        {
+            return false;
+        }
+        !(self.reuse_address && other.reuse_address || self.reuse_port && other.reuse_port)
+    }
+}
+
+/// Tracks the local ports held by sockets.
+///
+/// Ports are tracked per transport protocol, so a UDP socket and a TCP socket
+/// can hold the same port number.
+pub struct PortManager {
+    /// The bindings on each (protocol, port) pair.
+    bindings: BTreeMap<(Protocol, u16), Vec<Binding>>,
+    /// The range ephemeral ports are allocated from.
+    first: u16,
+    last: u16,
+    /// The next ephemeral port to try.
+    next: u16,
+}
+
+impl PortManager {
+    /// Create a new port manager allocating ephemeral ports from the inclusive
+    /// range `first` to `last`.
+    pub const fn new(first: u16, last: u16) -> Self {
+        PortManager {
+            bindings: BTreeMap::new(),
+            first: first,
+            last: last,
+            next: first,
+        }
+    }
+
+    /// Bind a socket to a specific port.
+    ///
+    /// Fails with `NetError::AddressInUse` if the port is held by a socket on
+    /// an overlapping address, unless both sockets set SO_REUSEADDR or both
+    /// set SO_REUSEPORT.
+    pub fn bind(
+        &mut self,
+        protocol: Protocol,
+        port: u16,
+        binding: Binding,
+    ) -> Result<(), NetError> {
+        let bindings = self.bindings.entry((protocol, port)).or_default();
+        if bindings.iter().any(|x| x.conflicts(&binding)) {
+            return Err(NetError::AddressInUse);
+        }
+        bindings.push(binding);
+        Ok(())
+    }
+
+    /// Bind a socket to a free port from the ephemeral range.
+    ///
+    /// Fails with `NetError::AddressNotAvailable` if every port in the range
+    /// is held.
+    pub fn allocate(&mut self, protocol: Protocol, binding: Binding) -> Result<u16, NetError> {
+        let count = (self.last - self.first) as u32 + 1;
+        for _ in 0..count {
+            let port = self.next;
+            self.next = if port == self.last {
+                self.first
+            } else {
+                port + 1
+            };
+
+            // Never share an ephemeral port, whatever the reuse options.
+            if !self.bindings.contains_key(&(protocol, port)) {
+                self.bindings.insert((protocol, port), alloc::vec![binding]);
+                return Ok(port);
+            }
+        }
+        Err(NetError::AddressNotAvailable)
+    }
+
+    /// Release the port held by a socket.
+    pub fn release(&mut self, protocol: Protocol, port: u16, socket: usize) {
+        if let Some(bindings) = self.bindings.get_mut(&(protocol, port)) {
+            bindings.retain(|x| x.socket != socket);
+            if bindings.is_empty() {
+                self.bindings.remove(&(protocol, port));
+            }
+        }
+    }
+}
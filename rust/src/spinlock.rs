@@ -1,9 +1,10 @@
 use core::cell::UnsafeCell;
+use core::ffi::c_void;
 use core::hint::spin_loop;
 use core::ops::{Deref, DerefMut};
 use core::sync::atomic::{AtomicBool, Ordering};
 
-use crate::kernel::{popcli, pushcli};
+use crate::kernel::{popcli, pushcli, sleepunlock};
 
 /// A simple spinlock implementation.
 pub struct Spinlock<T> {
@@ -44,6 +45,18 @@ impl<T> Spinlock<T> {
         }
     }
 
+    #[inline(always)]
+    fn unlock(&self) {
+        self.lock.store(false, Ordering::Release);
+        self.on_unlock();
+    }
+
+    /// Release a lock on behalf of sleepunlock(...) in proc.c.
+    unsafe extern "C" fn release(arg: *mut c_void) {
+        let spinlock = &*(arg as *const Spinlock<T>);
+        spinlock.unlock();
+    }
+
     #[inline(always)]
     fn on_lock(&self) {
         unsafe {
@@ -63,10 +76,28 @@ pub struct SpinlockGuard<'a, T: 'a> {
     spinlock: &'a Spinlock<T>,
 }
 
+impl<'a, T> SpinlockGuard<'a, T> {
+    /// Atomically release the lock and sleep on `chan`.
+    ///
+    /// Mirrors sleep(...) in proc.c, reacquiring the lock when awakened by a
+    /// wakeup(...) on the same channel.
+    pub fn sleep(self, chan: *const c_void) -> SpinlockGuard<'a, T> {
+        let spinlock = self.spinlock;
+        core::mem::forget(self);
+        unsafe {
+            sleepunlock(
+                chan,
+                Spinlock::<T>::release,
+                spinlock as *const Spinlock<T> as *mut c_void,
+            );
+        }
+        spinlock.lock()
+    }
+}
+
 impl<'a, T> Drop for SpinlockGuard<'a, T> {
     fn drop(&mut self) {
-        self.spinlock.lock.store(false, Ordering::Release);
-        self.spinlock.on_unlock();
+        self.spinlock.unlock();
     }
 }
 
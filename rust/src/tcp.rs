@@ -0,0 +1,727 @@
+use alloc::vec::Vec;
+use core::ops::BitOr;
+
+use crate::error::NetError;
+use crate::ip::{Ipv4Addr, Ipv4Packet, Protocol};
+use crate::packet_buffer::{FromBuffer, ToBuffer};
+
+/// The size of a header without options.
+const HEADER_SIZE: usize = 20;
+
+/// The most bytes of options a header holds, the data offset being 4 bits
+/// counting 32-bit words.
+// Nothing in the stack handles TCP yet, see `TcpSegment`.
+#[allow(dead_code)]
+const MAX_OPTIONS_SIZE: usize = 40;
+
+/// The control bits of a segment.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Flags(pub u16);
+
+// The full set of control bits, for the connection handling still to come.
+#[allow(dead_code)]
+impl Flags {
+    pub const FIN: Flags = Flags(0x001);
+    pub const SYN: Flags = Flags(0x002);
+    pub const RST: Flags = Flags(0x004);
+    pub const PSH: Flags = Flags(0x008);
+    pub const ACK: Flags = Flags(0x010);
+    pub const URG: Flags = Flags(0x020);
+    /// ECN-Echo, RFC3168.
+    pub const ECE: Flags = Flags(0x040);
+    /// Congestion Window Reduced, RFC3168.
+    pub const CWR: Flags = Flags(0x080);
+    /// ECN nonce, RFC3540, carried in the last reserved bit.
+    pub const NS: Flags = Flags(0x100);
+
+    /// Are all the bits of `other` set?
+    pub fn contains(&self, other: Flags) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for Flags {
+    type Output = Flags;
+
+    fn bitor(self, other: Flags) -> Flags {
+        Flags(self.0 | other.0)
+    }
+}
+
+/// A TCP option.
+///
+/// End of option list is not kept, parsing stops at it and the options are
+/// padded with it when written. No-operations are kept, so a parsed header
+/// is written back unchanged.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TcpOption {
+    NoOperation,
+    /// The largest segment the sender can receive, only sent with SYN.
+    ///
+    /// RFC793 Section 3.1
+    MaxSegmentSize(u16),
+    /// The shift applied to the window of every segment after the SYN.
+    ///
+    /// RFC7323 Section 2
+    WindowScale(u8),
+    /// Selective acknowledgements may be sent, only sent with SYN.
+    ///
+    /// RFC2018 Section 2
+    SackPermitted,
+    /// The left and right edges of blocks received past the acknowledgement
+    /// number.
+    ///
+    /// RFC2018 Section 3
+    Sack(Vec<(u32, u32)>),
+    /// RFC7323 Section 3
+    Timestamps {
+        value: u32,
+        echo_reply: u32,
+    },
+    /// An option the stack does not handle, with its kind and data.
+    Unknown(u8, Vec<u8>),
+}
+
+impl TcpOption {
+    /// Parse the option at the start of `buf`, returning it and its size, or
+    /// `None` at the end of the option list.
+    fn from_slice(buf: &[u8]) -> Result<Option<(TcpOption, usize)>, NetError> {
+        let kind = buf[0];
+        match kind {
+            0 => return Ok(None),
+            1 => return Ok(Some((TcpOption::NoOperation, 1))),
+            _ => (),
+        }
+
+        if buf.len() < 2 || (buf[1] as usize) < 2 || buf[1] as usize > buf.len() {
+            return Err(NetError::Malformed);
+        }
+        let len = buf[1] as usize;
+        let data = &buf[2..len];
+        let option = match (kind, len) {
+            (2, 4) => TcpOption::MaxSegmentSize(u16::from_be_bytes([data[0], data[1]])),
+            (3, 3) => TcpOption::WindowScale(data[0]),
+            (4, 2) => TcpOption::SackPermitted,
+            (5, _) if data.len() % 8 == 0 && data.len() > 0 => TcpOption::Sack(
+                data.chunks(8)
+                    .map(|x| {
+                        (
+                            u32::from_be_bytes([x[0], x[1], x[2], x[3]]),
+                            u32::from_be_bytes([x[4], x[5], x[6], x[7]]),
+                        )
+                    })
+                    .collect(),
+            ),
+            (8, 10) => TcpOption::Timestamps {
+                value: u32::from_be_bytes([data[0], data[1], data[2], data[3]]),
+                echo_reply: u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+            },
+            (2, _) | (3, _) | (4, _) | (5, _) | (8, _) => return Err(NetError::Malformed),
+            _ => TcpOption::Unknown(kind, data.to_vec()),
+        };
+        Ok(Some((option, len)))
+    }
+
+    /// The size of the option in bytes.
+    fn size(&self) -> usize {
+        match self {
+            TcpOption::NoOperation => 1,
+            TcpOption::MaxSegmentSize(_) => 4,
+            TcpOption::WindowScale(_) => 3,
+            TcpOption::SackPermitted => 2,
+            TcpOption::Sack(x) => 2 + x.len() * 8,
+            TcpOption::Timestamps { .. } => 10,
+            TcpOption::Unknown(_, x) => 2 + x.len(),
+        }
+    }
+
+    fn write(&self, buf: &mut [u8]) {
+        let size = self.size();
+        if size > 1 {
+            buf[1] = size as u8;
+        }
+        match self {
+            TcpOption::NoOperation => buf[0] = 1,
+            TcpOption::MaxSegmentSize(x) => {
+                buf[0] = 2;
+                buf[2..4].copy_from_slice(&x.to_be_bytes());
+            }
+            TcpOption::WindowScale(x) => {
+                buf[0] = 3;
+                buf[2] = *x;
+            }
+            TcpOption::SackPermitted => buf[0] = 4,
+            TcpOption::Sack(x) => {
+                buf[0] = 5;
+                for (i, (left, right)) in x.iter().enumerate() {
+                    let offset = 2 + i * 8;
+                    buf[offset..offset + 4].copy_from_slice(&left.to_be_bytes());
+                    buf[offset + 4..offset + 8].copy_from_slice(&right.to_be_bytes());
+                }
+            }
+            TcpOption::Timestamps { value, echo_reply } => {
+                buf[0] = 8;
+                buf[2..6].copy_from_slice(&value.to_be_bytes());
+                buf[6..10].copy_from_slice(&echo_reply.to_be_bytes());
+            }
+            TcpOption::Unknown(kind, x) => {
+                buf[0] = *kind;
+                buf[2..size].copy_from_slice(&x[..]);
+            }
+        }
+    }
+}
+
+/// A TCP segment.
+///
+/// The checksum covers a pseudo-header holding the addresses of the IPv4
+/// packet carrying the segment. Received segments are parsed with
+/// `TcpSegment::from_packet`, which checks the checksum against the addresses
+/// of their packet and keeps them. Segments to send are given the addresses to
+/// checksum over and the checksum is calculated on write.
+///
+/// RFC793 Section 3.1
+/// https://tools.ietf.org/html/rfc793
+#[derive(Debug, Clone)]
+pub struct TcpSegment {
+    pub source_port: u16,
+    pub dest_port: u16,
+    pub sequence_number: u32,
+    pub acknowledgment_number: u32,
+    pub flags: Flags,
+    pub window: u16,
+    /// The checksum as received, ignored on write.
+    #[allow(dead_code)]
+    pub checksum: u16,
+    pub urgent_pointer: u16,
+    /// Kept within the 40 bytes a header holds by `add_option`.
+    options: Vec<TcpOption>,
+    pub data: Vec<u8>,
+    /// The addresses of the pseudo-header, unspecified when parsed without
+    /// the packet carrying the segment.
+    pub source_address: Ipv4Addr,
+    pub dest_address: Ipv4Addr,
+}
+
+// Nothing in the stack handles TCP yet, segments are only built and parsed
+// in tests until connections are handled.
+#[allow(dead_code)]
+impl TcpSegment {
+    /// Create an empty segment from `source` to `dest`, with no flags set.
+    pub fn new(
+        source_address: Ipv4Addr,
+        source_port: u16,
+        dest_address: Ipv4Addr,
+        dest_port: u16,
+    ) -> TcpSegment {
+        TcpSegment {
+            source_port: source_port,
+            dest_port: dest_port,
+            sequence_number: 0,
+            acknowledgment_number: 0,
+            flags: Flags(0),
+            window: 0,
+            checksum: 0,
+            urgent_pointer: 0,
+            options: Vec::new(),
+            data: Vec::new(),
+            source_address: source_address,
+            dest_address: dest_address,
+        }
+    }
+
+    /// Parse the segment carried by `ip_packet`, whose payload is `buf`.
+    ///
+    /// Fails with `NetError::Malformed` if the segment is not intact. Any
+    /// padding of a short ethernet frame is dropped.
+    pub fn from_packet(ip_packet: &Ipv4Packet, buf: &[u8]) -> Result<TcpSegment, NetError> {
+        let len = (ip_packet.total_length() as usize).saturating_sub(ip_packet.header_size());
+        let buf = &buf[..len.min(buf.len())];
+        if TcpSegment::checksum(buf, ip_packet.source(), ip_packet.destination()) != 0 {
+            return Err(NetError::Malformed);
+        }
+
+        let mut segment = TcpSegment::from_slice(buf)?;
+        segment.source_address = ip_packet.source();
+        segment.dest_address = ip_packet.destination();
+        Ok(segment)
+    }
+
+    fn from_slice(buf: &[u8]) -> Result<TcpSegment, NetError> {
+        if buf.len() < HEADER_SIZE {
+            return Err(NetError::Malformed);
+        }
+
+        let header_size = (buf[12] >> 4) as usize * 4;
+        if header_size < HEADER_SIZE || header_size > buf.len() {
+            return Err(NetError::Malformed);
+        }
+
+        let mut options = Vec::new();
+        let mut offset = HEADER_SIZE;
+        while offset < header_size {
+            match TcpOption::from_slice(&buf[offset..header_size])? {
+                Some((option, size)) => {
+                    options.push(option);
+                    offset += size;
+                }
+                None => break,
+            }
+        }
+
+        Ok(TcpSegment {
+            source_port: u16::from_be_bytes([buf[0], buf[1]]),
+            dest_port: u16::from_be_bytes([buf[2], buf[3]]),
+            sequence_number: u32::from_be_bytes([buf[4], buf[5], buf[6], buf[7]]),
+            acknowledgment_number: u32::from_be_bytes([buf[8], buf[9], buf[10], buf[11]]),
+            flags: Flags(u16::from_be_bytes([buf[12] & 0x1, buf[13]])),
+            window: u16::from_be_bytes([buf[14], buf[15]]),
+            checksum: u16::from_be_bytes([buf[16], buf[17]]),
+            urgent_pointer: u16::from_be_bytes([buf[18], buf[19]]),
+            options: options,
+            data: buf[header_size..].to_vec(),
+            source_address: Ipv4Addr::new(0, 0, 0, 0),
+            dest_address: Ipv4Addr::new(0, 0, 0, 0),
+        })
+    }
+
+    pub fn options(&self) -> &[TcpOption] {
+        &self.options[..]
+    }
+
+    /// Append an option to the header.
+    ///
+    /// Fails with `NetError::InvalidArgument` if the options would no longer
+    /// fit in a header.
+    pub fn add_option(&mut self, option: TcpOption) -> Result<(), NetError> {
+        let size: usize = self.options.iter().map(|x| x.size()).sum();
+        if size + option.size() > MAX_OPTIONS_SIZE {
+            return Err(NetError::InvalidArgument);
+        }
+        self.options.push(option);
+        Ok(())
+    }
+
+    /// The sequence space the segment occupies, its data along with SYN and
+    /// FIN, which count as a byte each.
+    pub fn sequence_length(&self) -> u32 {
+        let mut len = self.data.len() as u32;
+        if self.flags.contains(Flags::SYN) {
+            len += 1;
+        }
+        if self.flags.contains(Flags::FIN) {
+            len += 1;
+        }
+        len
+    }
+
+    /// The reset answering a segment sent to a port with no socket, or `None`
+    /// if the segment is itself a reset, which is never answered.
+    ///
+    /// RFC793 Section 3.4
+    pub fn reset(&self) -> Option<TcpSegment> {
+        if self.flags.contains(Flags::RST) {
+            return None;
+        }
+
+        let mut reset = TcpSegment::new(
+            self.dest_address,
+            self.dest_port,
+            self.source_address,
+            self.source_port,
+        );
+        if self.flags.contains(Flags::ACK) {
+            reset.sequence_number = self.acknowledgment_number;
+            reset.flags = Flags::RST;
+        } else {
+            reset.acknowledgment_number = self.sequence_number.wrapping_add(self.sequence_length());
+            reset.flags = Flags::RST | Flags::ACK;
+        }
+        Some(reset)
+    }
+
+    /// The size of the header in bytes, the options padded to a multiple of 4
+    /// bytes included.
+    pub fn header_size(&self) -> usize {
+        let options: usize = self.options.iter().map(|x| x.size()).sum();
+        HEADER_SIZE + (options + 3) / 4 * 4
+    }
+
+    /// Calculate the checksum of the segment in `buf` sent from `source` to
+    /// `dest`.
+    ///
+    /// Over a segment with its checksum field zeroed this is the checksum to
+    /// send, over a received segment it is 0 if the segment is intact.
+    pub fn checksum(buf: &[u8], source: Ipv4Addr, dest: Ipv4Addr) -> u16 {
+        let mut pseudo_header = [0u8; 12];
+        pseudo_header[0..4].copy_from_slice(&source.as_bytes());
+        pseudo_header[4..8].copy_from_slice(&dest.as_bytes());
+        pseudo_header[9] = Protocol::TCP.as_bytes();
+        pseudo_header[10..12].copy_from_slice(&(buf.len() as u16).to_be_bytes());
+
+        let mut sum = 0u32;
+        for chunk in pseudo_header.chunks(2).chain(buf.chunks(2)) {
+            // An odd final byte is padded with zero.
+            let low = if chunk.len() > 1 { chunk[1] } else { 0 };
+            sum += u16::from_be_bytes([chunk[0], low]) as u32;
+        }
+
+        let check = (sum >> 16) + (sum & 0xffff);
+        let check = (check >> 16) + check;
+        !(check as u16)
+    }
+}
+
+impl FromBuffer for TcpSegment {
+    fn from_buffer(buf: &[u8]) -> Result<TcpSegment, NetError> {
+        TcpSegment::from_slice(&buf)
+    }
+
+    fn size(&self) -> usize {
+        self.header_size() + self.data.len()
+    }
+}
+
+impl ToBuffer for TcpSegment {
+    fn to_buffer(&self, buf: &mut [u8]) {
+        let header_size = self.header_size();
+
+        buf[0..2].copy_from_slice(&self.source_port.to_be_bytes());
+        buf[2..4].copy_from_slice(&self.dest_port.to_be_bytes());
+        buf[4..8].copy_from_slice(&self.sequence_number.to_be_bytes());
+        buf[8..12].copy_from_slice(&self.acknowledgment_number.to_be_bytes());
+        buf[12] = ((header_size / 4) as u8) << 4 | (self.flags.0 >> 8) as u8 & 0x1;
+        buf[13] = self.flags.0 as u8;
+        buf[14..16].copy_from_slice(&self.window.to_be_bytes());
+        buf[16..18].copy_from_slice(&0u16.to_be_bytes());
+        buf[18..20].copy_from_slice(&self.urgent_pointer.to_be_bytes());
+
+        let mut offset = HEADER_SIZE;
+        for option in self.options.iter() {
+            option.write(&mut buf[offset..]);
+            offset += option.size();
+        }
+        for x in buf[offset..header_size].iter_mut() {
+            *x = 0;
+        }
+
+        let size = header_size + self.data.len();
+        buf[header_size..size].copy_from_slice(&self.data[..]);
+
+        let checksum = TcpSegment::checksum(&buf[..size], self.source_address, self.dest_address);
+        buf[16..18].copy_from_slice(&checksum.to_be_bytes());
+    }
+
+    fn size(&self) -> usize {
+        self.header_size() + self.data.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    // Segments captured from Linux between 10.0.0.1 and 10.0.0.2, without the
+    // IPv4 header.
+
+    /// A SYN from 10.0.0.1 with MSS, SACK permitted, timestamps and window
+    /// scale options.
+    const SYN: [u8; 40] = [
+        0xc1, 0xe6, 0x00, 0x07, 0x1c, 0xac, 0xbf, 0x4d, 0x00, 0x00, 0x00, 0x00, 0xa0, 0x02, 0xfa,
+        0xf0, 0x78, 0xc0, 0x00, 0x00, 0x02, 0x04, 0x05, 0xb4, 0x04, 0x02, 0x08, 0x0a, 0xa4, 0x2e,
+        0x7e, 0x33, 0x00, 0x00, 0x00, 0x00, 0x01, 0x03, 0x03, 0x0a,
+    ];
+
+    /// A FIN from 10.0.0.1 without options.
+    const FIN: [u8; 20] = [
+        0xc1, 0xe6, 0x00, 0x07, 0x1c, 0xac, 0xbf, 0x58, 0x00, 0x00, 0x03, 0xe9, 0x50, 0x11, 0xfa,
+        0xf0, 0xff, 0x04, 0x00, 0x00,
+    ];
+
+    /// A duplicate ACK from 10.0.0.1 selectively acknowledging a block
+    /// received out of order.
+    const SACK: [u8; 44] = [
+        0x15, 0xb3, 0x9c, 0x40, 0x05, 0x54, 0x04, 0xf7, 0x00, 0x00, 0x13, 0x89, 0xb0, 0x10, 0x00,
+        0x40, 0xc4, 0xbe, 0x00, 0x00, 0x01, 0x01, 0x08, 0x0a, 0x6f, 0x88, 0xef, 0xb5, 0x00, 0x00,
+        0x10, 0x93, 0x01, 0x01, 0x05, 0x0a, 0x00, 0x00, 0x13, 0xed, 0x00, 0x00, 0x14, 0x1f,
+    ];
+
+    /// A segment from 10.0.0.1 carrying an odd number of bytes of data.
+    const DATA: [u8; 37] = [
+        0x15, 0xb3, 0x9c, 0x40, 0x05, 0x54, 0x04, 0xf7, 0x00, 0x00, 0x14, 0x1f, 0x80, 0x18, 0x00,
+        0x40, 0x35, 0x7c, 0x00, 0x00, 0x01, 0x01, 0x08, 0x0a, 0x6f, 0x88, 0xf3, 0x9f, 0x00, 0x00,
+        0x10, 0x95, 0x70, 0x6f, 0x6e, 0x67, 0x0a,
+    ];
+
+    fn source() -> Ipv4Addr {
+        Ipv4Addr::new(10, 0, 0, 1)
+    }
+
+    fn dest() -> Ipv4Addr {
+        Ipv4Addr::new(10, 0, 0, 2)
+    }
+
+    /// The packet that carried a captured segment of `len` bytes.
+    fn packet(len: usize) -> Ipv4Packet {
+        Ipv4Packet::new(
+            0,
+            0,
+            (len + 20) as u16,
+            0,
+            true,
+            false,
+            0,
+            64,
+            Protocol::TCP,
+            source(),
+            dest(),
+        )
+    }
+
+    fn write(segment: &TcpSegment) -> Vec<u8> {
+        let mut out = vec![0xffu8; ToBuffer::size(segment)];
+        segment.to_buffer(&mut out);
+        out
+    }
+
+    #[test]
+    fn test_from_buffer_syn() {
+        let segment = TcpSegment::from_buffer(&SYN).unwrap();
+        assert_eq!(segment.source_port, 49638);
+        assert_eq!(segment.dest_port, 7);
+        assert_eq!(segment.sequence_number, 0x1cacbf4d);
+        assert_eq!(segment.acknowledgment_number, 0);
+        assert_eq!(segment.flags, Flags::SYN);
+        assert_eq!(segment.window, 64240);
+        assert_eq!(segment.checksum, 0x78c0);
+        assert_eq!(segment.urgent_pointer, 0);
+        assert_eq!(
+            segment.options(),
+            [
+                TcpOption::MaxSegmentSize(1460),
+                TcpOption::SackPermitted,
+                TcpOption::Timestamps {
+                    value: 0xa42e7e33,
+                    echo_reply: 0,
+                },
+                TcpOption::NoOperation,
+                TcpOption::WindowScale(10),
+            ]
+        );
+        assert_eq!(segment.data.len(), 0);
+        assert_eq!(FromBuffer::size(&segment), 40);
+    }
+
+    #[test]
+    fn test_from_buffer_fin() {
+        let segment = TcpSegment::from_buffer(&FIN).unwrap();
+        assert_eq!(segment.sequence_number, 0x1cacbf58);
+        assert_eq!(segment.acknowledgment_number, 1001);
+        assert_eq!(segment.flags, Flags::FIN | Flags::ACK);
+        assert!(segment.flags.contains(Flags::ACK));
+        assert!(!segment.flags.contains(Flags::SYN));
+        assert_eq!(segment.options().len(), 0);
+        assert_eq!(segment.header_size(), 20);
+    }
+
+    #[test]
+    fn test_from_buffer_sack() {
+        let segment = TcpSegment::from_buffer(&SACK).unwrap();
+        assert_eq!(segment.acknowledgment_number, 5001);
+        assert_eq!(segment.flags, Flags::ACK);
+        assert_eq!(
+            segment.options(),
+            [
+                TcpOption::NoOperation,
+                TcpOption::NoOperation,
+                TcpOption::Timestamps {
+                    value: 0x6f88efb5,
+                    echo_reply: 4243,
+                },
+                TcpOption::NoOperation,
+                TcpOption::NoOperation,
+                TcpOption::Sack(vec![(5101, 5151)]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_from_buffer_data() {
+        let segment = TcpSegment::from_buffer(&DATA).unwrap();
+        assert_eq!(segment.flags, Flags::PSH | Flags::ACK);
+        assert_eq!(segment.header_size(), 32);
+        assert_eq!(segment.data, b"pong\n");
+    }
+
+    #[test]
+    fn test_checksum() {
+        for buf in [&SYN[..], &FIN[..], &SACK[..], &DATA[..]] {
+            assert_eq!(TcpSegment::checksum(buf, source(), dest()), 0);
+        }
+
+        // The addresses are covered by the pseudo-header.
+        assert_ne!(
+            TcpSegment::checksum(&SYN, source(), Ipv4Addr::new(10, 0, 0, 3)),
+            0
+        );
+
+        let mut buf = DATA;
+        buf[36] ^= 0x01;
+        assert_ne!(TcpSegment::checksum(&buf, source(), dest()), 0);
+    }
+
+    #[test]
+    fn test_from_packet() {
+        let segment = TcpSegment::from_packet(&packet(DATA.len()), &DATA).unwrap();
+        assert_eq!(segment.source_address, source());
+        assert_eq!(segment.dest_address, dest());
+        assert_eq!(segment.data, b"pong\n");
+
+        // The padding of a short ethernet frame is not data.
+        let mut buf = [0u8; 46];
+        buf[..FIN.len()].copy_from_slice(&FIN);
+        let segment = TcpSegment::from_packet(&packet(FIN.len()), &buf).unwrap();
+        assert_eq!(segment.data.len(), 0);
+
+        let mut buf = DATA;
+        buf[36] ^= 0x01;
+        assert_eq!(
+            TcpSegment::from_packet(&packet(DATA.len()), &buf).unwrap_err(),
+            NetError::Malformed
+        );
+    }
+
+    #[test]
+    fn test_to_buffer() {
+        for buf in [&SYN[..], &FIN[..], &SACK[..], &DATA[..]] {
+            let segment = TcpSegment::from_packet(&packet(buf.len()), buf).unwrap();
+            assert_eq!(write(&segment), buf);
+        }
+    }
+
+    #[test]
+    fn test_add_option() {
+        let mut segment = TcpSegment::new(source(), 7, dest(), 49638);
+        segment
+            .add_option(TcpOption::Sack(vec![(1, 2), (3, 4), (5, 6), (7, 8)]))
+            .unwrap();
+        segment.add_option(TcpOption::MaxSegmentSize(1460)).unwrap();
+        assert_eq!(segment.header_size(), 60);
+
+        assert_eq!(
+            segment.add_option(TcpOption::WindowScale(7)).unwrap_err(),
+            NetError::InvalidArgument
+        );
+        assert_eq!(segment.options().len(), 2);
+    }
+
+    #[test]
+    fn test_reset() {
+        let syn = TcpSegment::from_packet(&packet(SYN.len()), &SYN).unwrap();
+        let reset = syn.reset().unwrap();
+        assert_eq!(reset.source_address, dest());
+        assert_eq!(reset.source_port, 7);
+        assert_eq!(reset.dest_address, source());
+        assert_eq!(reset.dest_port, 49638);
+        assert_eq!(reset.sequence_number, 0);
+        assert_eq!(reset.acknowledgment_number, 0x1cacbf4e);
+        assert_eq!(reset.flags, Flags::RST | Flags::ACK);
+        assert_eq!(TcpSegment::checksum(&write(&reset), dest(), source()), 0);
+
+        let fin = TcpSegment::from_packet(&packet(FIN.len()), &FIN).unwrap();
+        let reset = fin.reset().unwrap();
+        assert_eq!(reset.sequence_number, 1001);
+        assert_eq!(reset.flags, Flags::RST);
+
+        // A reset is never answered.
+        assert!(reset.reset().is_none());
+    }
+
+    #[test]
+    fn test_to_buffer_padding() {
+        let mut segment = TcpSegment::new(source(), 7, dest(), 49638);
+        segment.sequence_number = 1000;
+        segment.acknowledgment_number = 0x1cacbf4e;
+        segment.flags = Flags::SYN | Flags::ACK;
+        segment.window = 8192;
+        segment.add_option(TcpOption::MaxSegmentSize(1460)).unwrap();
+        segment.add_option(TcpOption::WindowScale(7)).unwrap();
+
+        let mut buf = [0xffu8; 28];
+        assert_eq!(ToBuffer::size(&segment), 28);
+        segment.to_buffer(&mut buf);
+        assert_eq!(
+            buf,
+            [
+                0x00, 0x07, 0xc1, 0xe6, 0x00, 0x00, 0x03, 0xe8, 0x1c, 0xac, 0xbf, 0x4e, 0x70, 0x12,
+                0x20, 0x00, 0xa8, 0x3c, 0x00, 0x00, 0x02, 0x04, 0x05, 0xb4, 0x03, 0x03, 0x07, 0x00,
+            ]
+        );
+        assert_eq!(TcpSegment::checksum(&buf, source(), dest()), 0);
+
+        let parsed = TcpSegment::from_buffer(&buf).unwrap();
+        assert_eq!(parsed.options(), segment.options());
+    }
+
+    #[test]
+    fn test_from_buffer_malformed() {
+        // Shorter than a header.
+        assert_eq!(
+            TcpSegment::from_buffer(&FIN[..19]).unwrap_err(),
+            NetError::Malformed
+        );
+
+        // Data offset below the header size.
+        let mut buf = FIN;
+        buf[12] = 0x40;
+        assert!(TcpSegment::from_buffer(&buf).is_err());
+
+        // Data offset past the end of the segment.
+        let mut buf = FIN;
+        buf[12] = 0x60;
+        assert!(TcpSegment::from_buffer(&buf).is_err());
+
+        // Option running past the header.
+        let mut buf = SYN;
+        buf[39] = 0x0a;
+        buf[38] = 0x03;
+        buf[37] = 0x08;
+        assert!(TcpSegment::from_buffer(&buf).is_err());
+
+        // Option with the wrong length for its kind.
+        let mut buf = SYN;
+        buf[21] = 0x03;
+        assert!(TcpSegment::from_buffer(&buf).is_err());
+
+        // Option length below the kind and length bytes.
+        let mut buf = SYN;
+        buf[21] = 0x01;
+        assert!(TcpSegment::from_buffer(&buf).is_err());
+    }
+
+    #[test]
+    fn test_from_buffer_end_of_options() {
+        // Options after the end of the option list are ignored.
+        let mut buf = SYN;
+        buf[24] = 0x00;
+        let segment = TcpSegment::from_buffer(&buf).unwrap();
+        assert_eq!(segment.options(), [TcpOption::MaxSegmentSize(1460)]);
+    }
+
+    #[test]
+    fn test_unknown_option() {
+        // An MD5 signature option, RFC2385, is kept as it is.
+        let mut segment = TcpSegment::new(source(), 7, dest(), 49638);
+        segment
+            .add_option(TcpOption::Unknown(19, vec![0xab; 16]))
+            .unwrap();
+        let mut buf = [0u8; 40];
+        segment.to_buffer(&mut buf);
+        assert_eq!(&buf[20..24], [19, 18, 0xab, 0xab]);
+        assert_eq!(buf[38..40], [0, 0]);
+
+        let parsed = TcpSegment::from_buffer(&buf).unwrap();
+        assert_eq!(parsed.options(), segment.options());
+    }
+}
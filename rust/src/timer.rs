@@ -0,0 +1,62 @@
+use alloc::vec::Vec;
+use core::ffi::c_void;
+
+use crate::kernel::{ticks, wakeup};
+use crate::spinlock::Spinlock;
+
+/// A channel to wake up once a deadline has passed.
+struct Timer {
+    deadline: u32,
+    chan: usize,
+}
+
+/// Pending timers, at most one per channel.
+static TIMERS: Spinlock<Vec<Timer>> = Spinlock::new(Vec::new());
+
+/// The current tick count.
+pub fn now() -> u32 {
+    unsafe { core::ptr::read_volatile(core::ptr::addr_of!(ticks)) }
+}
+
+/// Has the tick count reached `deadline`?
+///
+/// Handles the tick count wrapping around.
+pub fn expired(deadline: u32) -> bool {
+    now().wrapping_sub(deadline) as i32 >= 0
+}
+
+/// Arrange for a wakeup(...) on `chan` once the tick count reaches `deadline`.
+///
+/// Lets a process sleeping on `chan` wake up to check for a timeout. If a timer
+/// is already pending on `chan`, the earlier of the two deadlines is kept and
+/// the sleeper is expected to rearm the timer when woken. Wakeups may be
+/// spurious, so sleepers must recheck their condition.
+pub fn wake_at(deadline: u32, chan: *const c_void) {
+    let mut timers = TIMERS.lock();
+    let chan = chan as usize;
+    match timers.iter_mut().find(|x| x.chan == chan) {
+        Some(x) => {
+            if (deadline.wrapping_sub(x.deadline) as i32) < 0 {
+                x.deadline = deadline;
+            }
+        }
+        None => timers.push(Timer { deadline, chan }),
+    }
+}
+
+/// Fire expired timers.
+///
+/// Called on every clock tick, wakes the channels of any timers that have
+/// expired. Timers fire on the tick their deadline passes and again on the
+/// following tick, so a sleeper that armed its timer on another CPU just as the
+/// deadline passed, but had not yet gone to sleep, is still woken.
+pub fn tick() {
+    let mut timers = TIMERS.lock();
+    timers.retain(|x| {
+        if !expired(x.deadline) {
+            return true;
+        }
+        unsafe { wakeup(x.chan as *const c_void) };
+        !expired(x.deadline.wrapping_add(1))
+    });
+}
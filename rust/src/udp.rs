@@ -1,5 +1,6 @@
 use alloc::vec::Vec;
 
+use crate::error::NetError;
 use crate::packet_buffer::{FromBuffer, ToBuffer};
 
 /// Represents a UDP packet header.
@@ -13,26 +14,34 @@ pub struct UdpPacket {
 }
 
 impl UdpPacket {
-    pub fn new(source_port: u16, dest_port: u16, data: Vec<u8>) -> Self {
-        UdpPacket {
+    /// The header of a datagram carrying `len` bytes of data, for data sent
+    /// without being copied into a packet first.
+    pub fn header(source_port: u16, dest_port: u16, len: usize) -> [u8; 8] {
+        let mut buf = [0u8; 8];
+        let header = UdpPacket {
             source_port: source_port,
             dest_port: dest_port,
-            len: (data.len() + 8) as u16,
+            len: (len + 8) as u16,
             checksum: 0,
-            data: data,
-        }
+            data: Vec::new(),
+        };
+        header.to_buffer(&mut buf);
+        buf
     }
 
-    fn from_slice(buf: &[u8]) -> Result<UdpPacket, ()> {
+    fn from_slice(buf: &[u8]) -> Result<UdpPacket, NetError> {
         if buf.len() < 8 {
             // This can't be a valid UDP packet.
-            return Err(());
+            return Err(NetError::Malformed);
         }
 
         let source_port = u16::from_be_bytes([buf[0], buf[1]]);
         let dest_port = u16::from_be_bytes([buf[2], buf[3]]);
         let len = u16::from_be_bytes([buf[4], buf[5]]);
         let checksum = u16::from_be_bytes([buf[6], buf[7]]);
+        if (len as usize) < 8 || len as usize > buf.len() {
+            return Err(NetError::Malformed);
+        }
         let data_len = (len - 8) as usize;
         let data = buf[8..(8 + data_len)].to_vec();
 
@@ -45,6 +54,10 @@ impl UdpPacket {
         })
     }
 
+    pub fn source_port(&self) -> u16 {
+        return self.source_port;
+    }
+
     pub fn dest_port(&self) -> u16 {
         return self.dest_port;
     }
@@ -55,7 +68,7 @@ impl UdpPacket {
 }
 
 impl FromBuffer for UdpPacket {
-    fn from_buffer(buf: &[u8]) -> Result<UdpPacket, ()> {
+    fn from_buffer(buf: &[u8]) -> Result<UdpPacket, NetError> {
         UdpPacket::from_slice(&buf)
     }
 
@@ -0,0 +1,149 @@
+// Address families, socket types and protocols for socket(...).
+#define AF_UNIX 1           // Local sockets named by a path
+#define AF_INET 2           // IPv4
+#define AF_PACKET 17        // Whole ethernet frames
+#define SOCK_STREAM 1       // Stream socket, AF_UNIX only
+#define SOCK_DGRAM 2        // Datagram socket
+#define SOCK_RAW 3          // Raw IPv4 socket for a single protocol
+#define SOCK_NONBLOCK 0x800 // Flag for the type, operations fail not block
+#define IPPROTO_ICMP 1      // Internet Control Message Protocol
+#define IPPROTO_IGMP 2      // Internet Group Management Protocol
+#define IPPROTO_UDP 17      // User Datagram Protocol
+#define IPPROTO_RAW 255     // Raw socket that only sends, implies IP_HDRINCL
+#define ETH_P_ALL 0x0003    // Packet socket for every Ethertype
+#define ETH_P_IP 0x0800     // Packet socket for IPv4 frames
+#define ETH_P_ARP 0x0806    // Packet socket for ARP frames
+
+#define INADDR_ANY 0x00000000       // Bind to any local address
+#define INADDR_BROADCAST 0xffffffff // Limited broadcast address
+
+typedef uint socklen_t;
+
+// A generic socket address.
+struct sockaddr {
+  ushort sa_family;
+  char sa_data[14];
+};
+
+struct in_addr {
+  uint s_addr; // Network byte order
+};
+
+// An IPv4 socket address.
+struct sockaddr_in {
+  ushort sin_family;       // AF_INET
+  ushort sin_port;         // Network byte order
+  struct in_addr sin_addr; // Network byte order
+  char sin_zero[8];
+};
+
+// A unix domain socket address.
+struct sockaddr_un {
+  ushort sun_family;  // AF_UNIX
+  char sun_path[108]; // NUL terminated path
+};
+
+// Convert between host (little endian) and network byte order.
+#define htons(x) ((ushort)((((x) & 0xff) << 8) | (((x) >> 8) & 0xff)))
+#define ntohs(x) htons(x)
+#define htonl(x)                                                             \
+  ((uint)((((x) & 0xff) << 24) | (((x) & 0xff00) << 8) |                     \
+          (((x) >> 8) & 0xff00) | (((x) >> 24) & 0xff)))
+#define ntohl(x) htonl(x)
+
+// Flags for send(...) and recv(...).
+#define MSG_PEEK 0x02     // Read data without removing it from the socket
+#define MSG_CTRUNC 0x08   // recvmsg(...) ancillary data did not fit
+#define MSG_TRUNC 0x20    // Return the full length of truncated data
+#define MSG_DONTWAIT 0x40 // Fail rather than block
+#define MSG_WAITALL 0x100 // Block until the full request is satisfied
+
+// Levels and names for setsockopt(...) and getsockopt(...). Options take
+// an int value unless noted. Timeouts are an int count of clock ticks, not a
+// struct timeval as on other systems.
+#define SOL_SOCKET 1    // Socket level options
+#define SO_REUSEADDR 2  // Allow binding a port held by another socket
+#define SO_ERROR 4      // Read and clear the pending error (read-only)
+#define SO_BROADCAST 6  // Allow sending to broadcast addresses
+#define SO_SNDBUF 7     // Largest datagram that can be sent, in bytes
+#define SO_RCVBUF 8     // Most bytes queued for receiving
+#define SO_REUSEPORT 15 // Allow several sockets to bind the same port
+#define SO_RCVTIMEO 20  // Receive timeout, 0 to wait forever
+#define SO_SNDTIMEO 21  // Address resolution timeout for sends
+#define SO_TIMESTAMP 29 // Report the arrival tick of data to recvmsg
+#define IPPROTO_IP 0    // IP level options
+#define IP_TOS 1        // Type of service of outgoing packets
+#define IP_TTL 2        // Time to live of outgoing packets
+#define IP_HDRINCL 3    // Raw socket data includes the IPv4 header
+#define IP_PKTINFO 8    // Report the destination address to recvmsg
+#define IP_MTU_DISCOVER 10 // Fragmentation of large datagrams, see below
+#define IP_RECVTTL 12   // Report the time to live to recvmsg
+#define IP_ADD_MEMBERSHIP 35  // Join a multicast group, a struct ip_mreq
+#define IP_DROP_MEMBERSHIP 36 // Leave a multicast group, a struct ip_mreq
+
+// Values of IP_MTU_DISCOVER.
+#define IP_PMTUDISC_DONT 0 // Fragment large datagrams, never set DF
+#define IP_PMTUDISC_WANT 1 // Fragment large datagrams, set DF on the rest
+#define IP_PMTUDISC_DO 2   // Always set DF, large datagrams fail with EMSGSIZE
+
+// A multicast group to join or leave on a UDP socket.
+struct ip_mreq {
+  struct in_addr imr_multiaddr; // The group
+  struct in_addr imr_interface; // INADDR_ANY or the local address
+};
+
+// A buffer for sendmsg(...) and recvmsg(...).
+struct iovec {
+  void *iov_base;
+  uint iov_len;
+};
+
+// A message for sendmsg(...) and recvmsg(...). Data is gathered from, or
+// scattered to, the msg_iovlen buffers of msg_iov, at most UIO_MAXIOV.
+struct msghdr {
+  void *msg_name;        // Optional address, as for sendto and recvfrom
+  uint msg_namelen;      // Size of the address
+  struct iovec *msg_iov; // Data buffers
+  uint msg_iovlen;       // Number of data buffers
+  void *msg_control;     // Ancillary data, recvmsg(...) only
+  uint msg_controllen;   // Size of the ancillary data buffer
+  int msg_flags;         // MSG_TRUNC and MSG_CTRUNC set by recvmsg(...)
+};
+
+#define UIO_MAXIOV 16
+
+// The header of an ancillary data message in msg_control.
+struct cmsghdr {
+  uint cmsg_len; // Size of the header and data
+  int cmsg_level;
+  int cmsg_type;
+};
+
+// Ancillary data returned by recvmsg(...), when enabled by the option of
+// the same level:
+//   SOL_SOCKET, SCM_TIMESTAMP - the uint tick the data arrived at
+//   IPPROTO_IP, IP_PKTINFO    - a struct in_pktinfo
+//   IPPROTO_IP, IP_TTL        - the int time to live of the datagram
+#define SCM_TIMESTAMP SO_TIMESTAMP
+
+// The addresses of a received datagram, reported by IP_PKTINFO.
+struct in_pktinfo {
+  int ipi_ifindex;             // Interface the datagram arrived on
+  struct in_addr ipi_spec_dst; // Local address of the interface
+  struct in_addr ipi_addr;     // Destination address of the datagram
+};
+
+// Walk the ancillary data of a struct msghdr.
+#define CMSG_ALIGN(len) (((len) + 3) & ~3)
+#define CMSG_LEN(len) (sizeof(struct cmsghdr) + (len))
+#define CMSG_SPACE(len) (sizeof(struct cmsghdr) + CMSG_ALIGN(len))
+#define CMSG_DATA(cmsg) ((uchar *)((struct cmsghdr *)(cmsg) + 1))
+#define CMSG_FIRSTHDR(msg)                                                   \
+  ((msg)->msg_controllen >= sizeof(struct cmsghdr)                           \
+       ? (struct cmsghdr *)(msg)->msg_control                                \
+       : (struct cmsghdr *)0)
+#define CMSG_NXTHDR(msg, cmsg)                                               \
+  ((uchar *)(cmsg) + CMSG_ALIGN((cmsg)->cmsg_len) + sizeof(struct cmsghdr) > \
+           (uchar *)(msg)->msg_control + (msg)->msg_controllen               \
+       ? (struct cmsghdr *)0                                                 \
+       : (struct cmsghdr *)((uchar *)(cmsg) + CMSG_ALIGN((cmsg)->cmsg_len)))
@@ -41,6 +41,17 @@ int fetchstr(uint addr, char **pp) {
   return -1;
 }
 
+// Check that the block of memory of size bytes at addr lies within
+// the address space of the current process, and point *pp at it.
+int fetchptr(uint addr, char **pp, int size) {
+  struct proc *curproc = myproc();
+
+  if (size < 0 || addr >= curproc->sz || addr + size > curproc->sz)
+    return -1;
+  *pp = (char *)addr;
+  return 0;
+}
+
 // Fetch the nth 32-bit system call argument.
 int argint(int n, int *ip) {
   return fetchint((myproc()->tf->esp) + 4 + 4 * n, ip);
@@ -51,14 +62,10 @@ int argint(int n, int *ip) {
 // lies within the process address space.
 int argptr(int n, char **pp, int size) {
   int i;
-  struct proc *curproc = myproc();
 
   if (argint(n, &i) < 0)
     return -1;
-  if (size < 0 || (uint)i >= curproc->sz || (uint)i + size > curproc->sz)
-    return -1;
-  *pp = (char *)i;
-  return 0;
+  return fetchptr(i, pp, size);
 }
 
 // Fetch the nth word-sized system call argument as a string pointer.
@@ -79,6 +86,9 @@ extern int sys_close(void);
 extern int sys_connect(void);
 extern int sys_dup(void);
 extern int sys_exec(void);
+extern int sys_epoll_create(void);
+extern int sys_epoll_ctl(void);
+extern int sys_epoll_wait(void);
 extern int sys_exit(void);
 extern int sys_fork(void);
 extern int sys_fstat(void);
@@ -90,13 +100,24 @@ extern int sys_mkdir(void);
 extern int sys_mknod(void);
 extern int sys_open(void);
 extern int sys_pipe(void);
+extern int sys_poll(void);
 extern int sys_read(void);
 extern int sys_recv(void);
+extern int sys_recvfrom(void);
+extern int sys_recvmsg(void);
+extern int sys_setsockopt(void);
+extern int sys_getsockopt(void);
+extern int sys_getsockname(void);
+extern int sys_getpeername(void);
 extern int sys_sbrk(void);
 extern int sys_send(void);
+extern int sys_sendfile(void);
+extern int sys_sendmsg(void);
+extern int sys_sendto(void);
 extern int sys_shutdown(void);
 extern int sys_sleep(void);
 extern int sys_socket(void);
+extern int sys_socketpair(void);
 extern int sys_unlink(void);
 extern int sys_wait(void);
 extern int sys_write(void);
@@ -119,6 +140,13 @@ static int (*syscalls[])(void) = {
     [SYS_listen] sys_listen, [SYS_connect] sys_connect,
     [SYS_accept] sys_accept, [SYS_send] sys_send,
     [SYS_recv] sys_recv,     [SYS_shutdown] sys_shutdown,
+    [SYS_poll] sys_poll,     [SYS_sendto] sys_sendto,
+    [SYS_recvfrom] sys_recvfrom, [SYS_setsockopt] sys_setsockopt,
+    [SYS_getsockopt] sys_getsockopt, [SYS_getsockname] sys_getsockname,
+    [SYS_getpeername] sys_getpeername, [SYS_socketpair] sys_socketpair,
+    [SYS_sendmsg] sys_sendmsg, [SYS_recvmsg] sys_recvmsg,
+    [SYS_sendfile] sys_sendfile, [SYS_epoll_create] sys_epoll_create,
+    [SYS_epoll_ctl] sys_epoll_ctl, [SYS_epoll_wait] sys_epoll_wait,
 };
 
 void syscall(void) {
@@ -28,3 +28,17 @@
 #define SYS_send 31
 #define SYS_recv 32
 #define SYS_shutdown 33
+#define SYS_poll 34
+#define SYS_sendto 35
+#define SYS_recvfrom 36
+#define SYS_setsockopt 37
+#define SYS_getsockopt 38
+#define SYS_getsockname 39
+#define SYS_getpeername 40
+#define SYS_socketpair 41
+#define SYS_sendmsg 42
+#define SYS_recvmsg 43
+#define SYS_sendfile 44
+#define SYS_epoll_create 45
+#define SYS_epoll_ctl 46
+#define SYS_epoll_wait 47
@@ -15,6 +15,7 @@
 #include "sleeplock.h"
 #include "file.h"
 #include "fcntl.h"
+#include "poll.h"
 
 // Fetch the nth word-sized system call argument as a file descriptor
 // and return both the descriptor and the corresponding struct file.
@@ -33,6 +34,35 @@ static int argfd(int n, int *pfd, struct file **pf) {
   return 0;
 }
 
+// Fetch the nth word-sized system call argument as a file descriptor
+// and return the corresponding struct file.
+int argfile(int n, struct file **pf) { return argfd(n, 0, pf); }
+
+// Fetch the nth word-sized system call argument as a socket descriptor
+// and return the network stack handle of the socket it refers to.
+// Fails if the socket is not owned by the calling process.
+int argsock(int n, int *psock) {
+  struct file *f;
+
+  if (argfd(n, 0, &f) < 0 || f->type != FD_SOCKET)
+    return -1;
+  if (!sockowned(f->sock, myproc()->pid))
+    return -1;
+  *psock = f->sock;
+  return 0;
+}
+
+// Fetch the nth word-sized system call argument as an epoll
+// descriptor and return the network stack epoll instance handle.
+int argepoll(int n, int *pep) {
+  struct file *f;
+
+  if (argfd(n, 0, &f) < 0 || f->type != FD_EPOLL)
+    return -1;
+  *pep = f->epoll;
+  return 0;
+}
+
 // Allocate a file descriptor for the given file.
 // Takes over file reference from caller on success.
 static int fdalloc(struct file *f) {
@@ -406,3 +436,162 @@ int sys_pipe(void) {
   fd[1] = fd1;
   return 0;
 }
+
+int sys_socket(void) {
+  int domain, type, protocol, sock;
+
+  if (argint(0, &domain) < 0 || argint(1, &type) < 0 || argint(2, &protocol) < 0)
+    return -1;
+  if ((sock = sockalloc(domain, type, protocol, myproc()->pid)) < 0)
+    return sock;
+  return sockfdalloc(sock);
+}
+
+int sys_socketpair(void) {
+  int *sv;
+  int domain, type, protocol, err, sock[2], fd0, fd1;
+  struct file *f;
+
+  if (argint(0, &domain) < 0 || argint(1, &type) < 0 ||
+      argint(2, &protocol) < 0 || argptr(3, (void *)&sv, 2 * sizeof(sv[0])) < 0)
+    return -1;
+  if ((err = sockpair(domain, type, protocol, myproc()->pid, sock)) < 0)
+    return err;
+  if ((fd0 = sockfdalloc(sock[0])) < 0) {
+    sockclose(sock[1]);
+    return -1;
+  }
+  if ((fd1 = sockfdalloc(sock[1])) < 0) {
+    f = myproc()->ofile[fd0];
+    myproc()->ofile[fd0] = 0;
+    fileclose(f);
+    return -1;
+  }
+  sv[0] = fd0;
+  sv[1] = fd1;
+  return 0;
+}
+
+// Allocate a file descriptor for a network stack socket.
+// Releases the socket and returns -1 if no file or descriptor is free.
+int sockfdalloc(int sock) {
+  struct file *f;
+  int fd;
+
+  if ((f = filealloc()) == 0 || (fd = fdalloc(f)) < 0) {
+    if (f)
+      fileclose(f);
+    sockclose(sock);
+    return -1;
+  }
+
+  f->type = FD_SOCKET;
+  f->sock = sock;
+  f->off = 0;
+  f->readable = 1;
+  f->writable = 1;
+  return fd;
+}
+
+// Allocate a file descriptor for a network stack epoll instance.
+// Releases the instance and returns -1 if no file or descriptor is free.
+int epollfdalloc(int ep) {
+  struct file *f;
+  int fd;
+
+  if ((f = filealloc()) == 0 || (fd = fdalloc(f)) < 0) {
+    if (f)
+      fileclose(f);
+    epollclose(ep);
+    return -1;
+  }
+
+  f->type = FD_EPOLL;
+  f->epoll = ep;
+  f->off = 0;
+  f->readable = 0;
+  f->writable = 0;
+  return fd;
+}
+
+// Create the socket inode a unix domain socket binds to.
+// Returns the inode, referenced but unlocked, or 0 if path
+// already exists or its directory does not.
+struct inode *sockcreate(char *path) {
+  struct inode *ip;
+
+  begin_op();
+  if ((ip = create(path, T_SOCK, 0, 0)) != 0)
+    iunlock(ip);
+  end_op();
+  return ip;
+}
+
+// Look up the inode a unix domain socket connects to.
+// Returns the inode, referenced but unlocked, or 0 if
+// there is none.
+struct inode *socklookup(char *path) {
+  struct inode *ip;
+
+  begin_op();
+  ip = namei(path);
+  end_op();
+  return ip;
+}
+
+// Release an inode returned by sockcreate or socklookup.
+void sockiput(struct inode *ip) {
+  begin_op();
+  iput(ip);
+  end_op();
+}
+
+// Wait for one of a set of file descriptors to become ready.
+// The timeout is in clock ticks; a negative timeout waits forever.
+int sys_poll(void) {
+  struct pollfd *fds;
+  struct file *f;
+  int i, n, nfds, timeout;
+  uint seq, start;
+
+  if (argint(1, &nfds) < 0 || argint(2, &timeout) < 0)
+    return -1;
+  if (nfds < 0 || nfds > NOFILE)
+    return -1;
+  if (argptr(0, (void *)&fds, nfds * sizeof(fds[0])) < 0)
+    return -1;
+
+  acquire(&tickslock);
+  start = ticks;
+  release(&tickslock);
+
+  for (;;) {
+    seq = pollseq();
+    n = 0;
+    for (i = 0; i < nfds; i++) {
+      fds[i].revents = 0;
+      if (fds[i].fd < 0)
+        continue;
+      if (fds[i].fd >= NOFILE || (f = myproc()->ofile[fds[i].fd]) == 0)
+        fds[i].revents = POLLNVAL;
+      else
+        fds[i].revents =
+            filepoll(f) & (fds[i].events | POLLERR | POLLHUP);
+      if (fds[i].revents)
+        n++;
+    }
+    if (n > 0 || timeout == 0)
+      return n;
+    if (myproc()->killed)
+      return -1;
+    if (timeout > 0) {
+      acquire(&tickslock);
+      if (ticks - start >= timeout) {
+        release(&tickslock);
+        return 0;
+      }
+      release(&tickslock);
+    }
+    pollsleep(seq, timeout > 0);
+  }
+}
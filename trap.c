@@ -9,6 +9,7 @@
 #include "spinlock.h"
 
 void netintr();
+void nettick();
 
 // Interrupt descriptor table (shared by all CPUs).
 struct gatedesc idt[256];
@@ -47,6 +48,8 @@ void trap(struct trapframe *tf) {
       ticks++;
       wakeup(&ticks);
       release(&tickslock);
+      polltick();
+      nettick();
     }
     lapiceoi();
     break;
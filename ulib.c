@@ -2,6 +2,7 @@
 #include "stat.h"
 #include "fcntl.h"
 #include "user.h"
+#include "socket.h"
 #include "x86.h"
 
 char *strcpy(char *s, const char *t) {
@@ -86,3 +87,30 @@ void *memmove(void *vdst, const void *vsrc, int n) {
     *dst++ = *src++;
   return vdst;
 }
+
+// Parse an IPV4 address from its 'dot' representation, returning it
+// in network byte order.
+uint inet_addr(const char *addr) {
+  uint target_addr = 0x0;
+  uint shift = 24;
+  uint addr_len = strlen(addr);
+  for (int i = 0; i < addr_len; i++) {
+    char part[4] = {0x0, 0x0, 0x0, 0x0};
+    int sep = i;
+    for (int j = i; j < addr_len; j++) {
+      if (addr[j] == '.') {
+        sep = j;
+        break;
+      }
+      sep = j + 1; // No separator after last octet.
+    }
+    memmove(&part, addr + i, sep - i);
+
+    int octet = atoi(part);
+    target_addr |= octet << (shift);
+    shift -= 8;
+
+    i = sep;
+  }
+  return htonl(target_addr);
+}
@@ -1,5 +1,9 @@
 struct stat;
 struct rtcdate;
+struct pollfd;
+struct sockaddr;
+struct msghdr;
+struct epoll_event;
 
 // system calls
 int fork(void);
@@ -23,14 +27,59 @@ int getpid(void);
 char *sbrk(int);
 int sleep(int);
 int uptime(void);
-int socket(int);
-int bind(int, int, int);
-int connect(int, int, int);
-int listen();
-int accept();
-int send(int, const void *, int);
-int recv(int, const void *, int);
+int socket(int, int, int);
+int bind(int, const struct sockaddr *, uint);
+int connect(int, const struct sockaddr *, uint);
+int listen(int, int);
+int accept(int, struct sockaddr *, uint *);
+int send(int, const void *, int, int);
+int recv(int, void *, int, int);
 int shutdown(int);
+int poll(struct pollfd *, int, int);
+int sendto(int, const void *, int, int, const struct sockaddr *, uint);
+int recvfrom(int, void *, int, int, struct sockaddr *, uint *);
+int setsockopt(int, int, int, const void *, uint);
+int getsockopt(int, int, int, void *, uint *);
+int getsockname(int, struct sockaddr *, uint *);
+int getpeername(int, struct sockaddr *, uint *);
+int socketpair(int, int, int, int *);
+int sendmsg(int, const struct msghdr *, int);
+int recvmsg(int, struct msghdr *, int);
+int sendfile(int, int, uint, int);
+int epoll_create(int);
+int epoll_ctl(int, int, int, struct epoll_event *);
+int epoll_wait(int, struct epoll_event *, int, int);
+
+// Network error codes. Socket system calls return the negated code on
+// failure, e.g. bind(...) returns -EADDRINUSE.
+#define ENOENT 2           // No such unix domain socket path or registration
+#define EINTR 4            // Interrupted by kill(...)
+#define EBADF 9            // Not a socket owned by the process
+#define EAGAIN 11          // Would block on a non-blocking socket
+#define EACCES 13          // Broadcast destination without SO_BROADCAST
+#define EFAULT 14          // Bad user pointer
+#define EEXIST 17          // Socket already registered with the epoll instance
+#define EINVAL 22          // Invalid argument
+#define ENFILE 23          // System socket limit reached
+#define EMFILE 24          // Per-process socket limit reached
+#define EPIPE 32           // Socket or its stream remote has been shut down
+#define EBADMSG 74         // Malformed packet
+#define EMSGSIZE 90        // Datagram larger than SO_SNDBUF
+#define ENOPROTOOPT 92     // Unknown or read-only socket option
+#define EPROTONOSUPPORT 93 // Unsupported domain, type and protocol
+#define EOPNOTSUPP 95      // Operation not supported by the socket
+#define EAFNOSUPPORT 97    // Address family does not match the socket
+#define EADDRINUSE 98      // Port already bound by another socket
+#define EADDRNOTAVAIL 99   // No free ephemeral ports
+#define ENETDOWN 100       // No network device
+#define ENOBUFS 105        // Network device transmit ring full
+#define EISCONN 106        // Socket is already connected
+#define ENOTCONN 107       // Socket is not connected
+#define ETIMEDOUT 110      // SO_RCVTIMEO or SO_SNDTIMEO passed
+#define ECONNREFUSED 111   // Remote port unreachable
+#define EHOSTUNREACH 113   // Remote address did not resolve
+#define EALREADY 114       // connect(...) already in progress
+#define EINPROGRESS 115    // Non-blocking connect still resolving the remote
 
 // ulib.c
 int stat(const char *, struct stat *);
@@ -45,3 +94,4 @@ void *memset(void *, int, uint);
 void *malloc(uint);
 void free(void *);
 int atoi(const char *);
+uint inet_addr(const char *);